@@ -1,6 +1,12 @@
-use clap::{App, Arg};
-use gml_fmt_lib::{Config, LangConfig, PrintFlags};
-use std::{path::PathBuf, process};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use gml_fmt_lib::{
+    Config, ConfigError, ConfigResolver, DumpFormat, FormatError, PrintFlags, Project, ProjectError, ProjectErrorKind,
+};
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
@@ -31,19 +37,153 @@ fn main() {
                 .short("n")
                 .help("Do not overwrite the original file. Mostly used in conjungtion with -l to log output."),
         )
+        .arg(
+            Arg::with_name("backup")
+                .long("backup")
+                .help("Keeps the original of every file changed in a timestamped folder inside .gml_fmt_backup."),
+        )
+        .arg(Arg::with_name("verify").long("verify").help(
+            "Checks that each file's code and comments are unchanged by formatting, and leaves any which fail alone.",
+        ))
+        .arg(
+            Arg::with_name("check-idempotence")
+                .long("check-idempotence")
+                .help("Formats each file's output a second time, and leaves any file whose output changes alone."),
+        )
+        .arg(
+            Arg::with_name("restore")
+                .long("restore")
+                .help("Puts back the files from the latest backup in PATH, then removes that backup, and exits."),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("CONFIG")
+                .takes_value(true)
+                .help("Uses this config file instead of searching for one."),
+        )
+        .arg(
+            Arg::with_name("config-override")
+                .long("config-override")
+                .value_name("KEY=VALUE")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Overrides a single config option, such as `space_size=2`. May be given more than once."),
+        )
+        .arg(
+            Arg::with_name("include")
+                .long("include")
+                .value_name("GLOB")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only formats files matching this glob, such as `scripts/**`. May be given more than once."),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .value_name("GLOB")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Skips files and folders matching this glob, such as `datafiles`. May be given more than once."),
+        )
+        .arg(
+            Arg::with_name("project")
+                .long("project")
+                .help("Formats the scripts and objects listed in the .yyp in PATH, instead of every .gml file."),
+        )
+        .arg(
+            Arg::with_name("resource")
+                .long("resource")
+                .value_name("NAME")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Formats only the script or object with this name. Implies --project. May be given more than once.",
+                ),
+        )
+        .arg(
+            Arg::with_name("print-config")
+                .long("print-config")
+                .help("Prints the configuration used for PATH, with where each option came from, and exits."),
+        )
+        .subcommand(dump_command(
+            "dump-ast",
+            "Prints the AST of a file, without formatting it",
+        ))
+        .subcommand(dump_command(
+            "dump-tokens",
+            "Prints every token in a file, with its position",
+        ))
         .get_matches();
 
-    // Get our path and make our lang_config file
-    let our_path = std::env::current_dir().unwrap();
-    let lang_config = LangConfig::new(&our_path);
+    if let (name @ "dump-ast", Some(dump_matches)) | (name @ "dump-tokens", Some(dump_matches)) = matches.subcommand() {
+        match dump(name, dump_matches) {
+            // Scripts often only read the start of the output, which is fine by us.
+            Ok(output) => match writeln!(io::stdout(), "{}", output) {
+                Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+                    eprintln!("Error: {}", e);
+                    process::exit(1);
+                }
+                _ => {}
+            },
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Get our path
+    let our_path = std::env::current_dir().unwrap_or_else(|e| {
+        eprintln!("Could not find the current directory: {}", e);
+        process::exit(1);
+    });
 
     // Get Path
-    let input_path = if matches.is_present("PATH") {
-        PathBuf::from(matches.value_of("PATH").unwrap())
-    } else {
-        our_path
+    let input_path = match matches.value_of("PATH") {
+        Some(path) => PathBuf::from(path),
+        None => our_path.clone(),
     };
 
+    if matches.is_present("restore") {
+        match gml_fmt_lib::restore_latest(&input_path) {
+            Ok(Some(restored)) => {
+                for file in &restored.files {
+                    println!("Restored {:?}", file);
+                }
+                println!("Restored {} files from {:?}.", restored.files.len(), restored.backup);
+            }
+            Ok(None) => println!("There are no backups in {:?}.", input_path),
+            Err(e) => {
+                eprintln!("Could not restore the backup: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Restoring doesn't read the config, so a broken one can't stand in its way.
+    let config_resolver = make_config_resolver(&our_path, &matches).unwrap_or_else(|e| {
+        eprintln!("Config error: {}", e);
+        process::exit(1);
+    });
+
+    if matches.is_present("print-config") {
+        match config_resolver.resolve(&input_path) {
+            Ok(resolved) => print!("{}", resolved.to_toml()),
+            Err(e) => {
+                eprintln!("Config error: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
     // Is it a file?
     let do_file = matches.is_present("file");
 
@@ -58,20 +198,58 @@ fn main() {
         print_flags.insert(PrintFlags::LOGS);
     }
 
-    if matches.is_present("log") {
-        print_flags.insert(PrintFlags::LOGS);
+    if matches.is_present("backup") {
+        print_flags.insert(PrintFlags::BACKUP);
+    }
+
+    if matches.is_present("verify") {
+        print_flags.insert(PrintFlags::VERIFY);
+    }
+
+    if matches.is_present("check-idempotence") {
+        print_flags.insert(PrintFlags::CHECK_IDEMPOTENCE);
     }
 
     if matches.is_present("log-ast") {
         print_flags.insert(PrintFlags::LOG_AST);
     }
 
-    let config = Config::new(input_path, print_flags, do_file).unwrap_or_else(|e| {
-        eprintln!("File reading error: {}", e);
-        process::exit(1);
-    });
+    let globs = |name| -> Vec<String> {
+        matches
+            .values_of(name)
+            .map(|values| values.map(str::to_owned).collect())
+            .unwrap_or_default()
+    };
+
+    let config = if matches.is_present("project") || matches.is_present("resource") {
+        read_project(&input_path)
+            .and_then(|project| {
+                Config::from_project(
+                    &project,
+                    print_flags,
+                    &globs("resource"),
+                    &globs("include"),
+                    &globs("exclude"),
+                )
+            })
+            .unwrap_or_else(|e| {
+                eprintln!("Project error: {}", e);
+                process::exit(1);
+            })
+    } else {
+        Config::with_filters(input_path, print_flags, do_file, &globs("include"), &globs("exclude")).unwrap_or_else(
+            |e| {
+                eprintln!("File reading error: {}", e);
+                process::exit(1);
+            },
+        )
+    };
 
-    match gml_fmt_lib::run_with_config(&config, &lang_config) {
+    for warning in &config.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    match gml_fmt_lib::run_with_config(&config, &config_resolver) {
         Ok(()) => {
             println!("Format complete.");
         }
@@ -81,3 +259,64 @@ fn main() {
         }
     };
 }
+
+fn dump_command<'a, 'b>(name: &'a str, about: &'a str) -> App<'a, 'b> {
+    SubCommand::with_name(name)
+        .about(about)
+        .arg(Arg::with_name("PATH").help("The file to read.").required(true).index(1))
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["json", "debug"])
+                .default_value("json")
+                .help("Prints JSON for scripts, or Rust's debug output."),
+        )
+}
+
+fn dump(name: &str, matches: &ArgMatches<'_>) -> Result<String, FormatError> {
+    let path = matches.value_of("PATH").expect("clap makes PATH required for the dump commands");
+    let source = gml_fmt_lib::read_source(Path::new(path))?;
+    let format = match matches.value_of("format") {
+        Some("debug") => DumpFormat::Debug,
+        _ => DumpFormat::Json,
+    };
+
+    match name {
+        "dump-ast" => gml_fmt_lib::dump_ast(&source, format),
+        _ => gml_fmt_lib::dump_tokens(&source, format),
+    }
+}
+
+// PATH can be the `.yyp` itself, or the folder it is in.
+fn read_project(input_path: &Path) -> Result<Project, ProjectError> {
+    let yyp_path = if input_path.is_file() {
+        Some(input_path.to_owned())
+    } else {
+        Project::find(input_path)
+    };
+
+    match yyp_path {
+        Some(yyp_path) => Project::read(&yyp_path),
+        None => Err(ProjectError {
+            path: input_path.to_owned(),
+            kind: ProjectErrorKind::NoProject,
+        }),
+    }
+}
+
+fn make_config_resolver(our_path: &Path, matches: &ArgMatches<'_>) -> Result<ConfigResolver, ConfigError> {
+    let mut config_resolver = ConfigResolver::new(our_path)?;
+
+    if let Some(config_path) = matches.value_of("config") {
+        config_resolver.set_config_file(Path::new(config_path))?;
+    }
+
+    if let Some(overrides) = matches.values_of("config-override") {
+        for key_value in overrides {
+            config_resolver.add_override(key_value)?;
+        }
+    }
+
+    Ok(config_resolver)
+}
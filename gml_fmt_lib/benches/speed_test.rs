@@ -2,7 +2,7 @@
 extern crate criterion;
 
 use criterion::Criterion;
-use gml_fmt_lib::{Config, LangConfig, PrintFlags};
+use gml_fmt_lib::{Config, ConfigResolver, PrintFlags};
 use std::{path::PathBuf, process};
 
 fn lex_test() {
@@ -12,12 +12,12 @@ fn lex_test() {
         process::exit(1);
     });
 
-    gml_fmt_lib::run_with_config(&config, &LangConfig::default())
+    gml_fmt_lib::run_with_config(&config, &ConfigResolver::default())
         .expect("Attempted to run osg_lex_speed test, but failed. Did you move the file?");
 }
 
 fn criterion_benchmark(c: &mut Criterion) {
-    c.bench_function("osg_lex_speed", |b| b.iter(|| lex_test()));
+    c.bench_function("osg_lex_speed", |b| b.iter(lex_test));
 }
 
 criterion_group!(benches, criterion_benchmark);
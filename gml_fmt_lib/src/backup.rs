@@ -0,0 +1,203 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, process};
+
+const BACKUP_FOLDER_NAME: &str = ".gml_fmt_backup";
+
+/// Copies of the files a run changed, kept in `.gml_fmt_backup/<time of the run>` inside the
+/// folder the files were found in. The folder is hidden, so we never walk into it.
+pub(crate) struct Backup {
+    root: PathBuf,
+    folder: Option<PathBuf>,
+}
+
+/// What `restore_latest` put back.
+#[derive(Debug)]
+pub struct Restored {
+    pub backup: PathBuf,
+    pub files: Vec<PathBuf>,
+}
+
+impl Backup {
+    pub fn new(root: &Path) -> Backup {
+        Backup {
+            root: root.to_owned(),
+            folder: None,
+        }
+    }
+
+    /// Keeps `contents`, the original of `path`. The folder is only made when the first
+    /// file is saved, so runs which change nothing leave nothing behind.
+    pub fn save(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let path = fs::canonicalize(path)?;
+        let relative_path = path
+            .strip_prefix(&self.root)
+            .map_err(|_| io::Error::other(format!("it is outside of {:?}, where the backup is", self.root)))?;
+
+        let folder = match &self.folder {
+            Some(folder) => folder.clone(),
+            None => {
+                let folder = Backup::make_folder(&self.root.join(BACKUP_FOLDER_NAME))?;
+                self.folder = Some(folder.clone());
+                folder
+            }
+        };
+
+        let backup_path = folder.join(relative_path);
+        if let Some(parent) = backup_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(backup_path, contents)
+    }
+
+    // Named by the time in UTC, so the latest backup sorts last.
+    fn make_folder(backups: &Path) -> io::Result<PathBuf> {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let timestamp = timestamp(seconds);
+
+        fs::create_dir_all(backups)?;
+        let mut attempt = 1;
+        loop {
+            let name = match attempt {
+                1 => timestamp.clone(),
+                _ => format!("{}_{}", timestamp, attempt),
+            };
+
+            let folder = backups.join(name);
+            match fs::create_dir(&folder) {
+                Ok(()) => return Ok(folder),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Puts back every file in the latest backup in `folder`, then removes that backup, so
+/// the next restore goes one run further back. Returns `None` if there are no backups.
+pub fn restore_latest(folder: &Path) -> io::Result<Option<Restored>> {
+    let root = fs::canonicalize(folder)?;
+    let backups = root.join(BACKUP_FOLDER_NAME);
+    if backups.is_dir() == false {
+        return Ok(None);
+    }
+
+    let mut runs = Vec::new();
+    for entry in fs::read_dir(&backups)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            runs.push(path);
+        }
+    }
+    // A second backup in the same second gets a `_2`, which sorts after the first.
+    runs.sort_by_key(|path| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let (timestamp, attempt) = match name.rfind('_') {
+            Some(i) if i > 10 => (name[..i].to_owned(), name[i + 1..].parse().unwrap_or(0)),
+            _ => (name, 1),
+        };
+        (timestamp, attempt)
+    });
+
+    let backup = match runs.pop() {
+        Some(backup) => backup,
+        None => return Ok(None),
+    };
+
+    let mut backup_files = Vec::new();
+    collect_files(&backup, &mut backup_files)?;
+    backup_files.sort();
+
+    let mut files = Vec::new();
+    for backup_file in backup_files {
+        if let Ok(relative_path) = backup_file.strip_prefix(&backup) {
+            let path = root.join(relative_path);
+            write_atomically(&path, &fs::read(&backup_file)?)?;
+            files.push(path);
+        }
+    }
+
+    fs::remove_dir_all(&backup)?;
+    Ok(Some(Restored { backup, files }))
+}
+
+/// Writes `contents` to a file next to `path`, then renames it over `path`, so a crash or a
+/// full disk can't leave `path` half written.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{}.{}.gml_fmt", file_name, process::id()));
+
+    let result = (|| {
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+        }
+        fs::rename(&temp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn collect_files(folder: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// `2019-08-24_13-05-09` for a count of seconds since 1970, in UTC.
+fn timestamp(seconds: u64) -> String {
+    let days = seconds / 86400;
+    let time = seconds % 86400;
+
+    // From the days since 1970 to a date, counting in 400 year eras which start in March.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod backup_test {
+    use super::timestamp;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(timestamp(0), "1970-01-01_00-00-00");
+        assert_eq!(timestamp(951_782_400), "2000-02-29_00-00-00");
+        assert_eq!(timestamp(1_566_651_909), "2019-08-24_13-05-09");
+    }
+}
@@ -0,0 +1,285 @@
+use super::expressions::*;
+use super::lex_token::{Span, Token, TokenType};
+use super::statements::*;
+use super::visitor::{walk_ast_mut, walk_expr_mut, walk_statement_mut, VisitorMut};
+
+/// Wraps the single statement bodies of `if`, `else`, `for`, `while`, `with`, `repeat`
+/// and `do` in blocks. An `else if` is left as it is, so chains stay flat.
+pub struct BraceInserter;
+
+impl BraceInserter {
+    pub fn insert_braces(ast: &mut Vec<StmtBox<'_>>) {
+        BraceInserter::wrap_statements(ast);
+        walk_ast_mut(&mut BraceInserter, ast);
+    }
+
+    // A comment written after a statement, on its line, is parsed as the next statement.
+    // When the statement ends in a body we wrap, the comment has to move into the new
+    // block, or it would land after the `}` and comment out whatever follows.
+    fn wrap_statements<'a>(statements: &mut Vec<StmtBox<'a>>) {
+        let mut i = 0;
+        while i < statements.len() {
+            let same_line_comments: Vec<Token<'a>> = statements[i + 1..]
+                .iter()
+                .take_while(|stmt| {
+                    matches!(
+                        stmt.statement,
+                        Statement::Comment { .. } | Statement::MultilineComment { .. }
+                    )
+                })
+                .filter_map(|stmt| match stmt.statement {
+                    Statement::Comment { comment } => Some(comment),
+                    Statement::MultilineComment { multiline_comment } => Some(multiline_comment),
+                    _ => None,
+                })
+                .collect();
+            let count = same_line_comments.len();
+
+            if BraceInserter::wrap_bodies(&mut statements[i], same_line_comments).is_empty() {
+                statements.drain(i + 1..i + 1 + count);
+            }
+            i += 1;
+        }
+    }
+
+    // Wraps every body of `stmt`. The comments which followed `stmt` on its line go into
+    // its last body, and are handed back when `stmt` doesn't end in a body we wrap.
+    fn wrap_bodies<'a>(stmt: &mut StatementWrapper<'a>, same_line_comments: Vec<Token<'a>>) -> Vec<Token<'a>> {
+        match &mut stmt.statement {
+            Statement::If {
+                condition,
+                then_branch,
+                comments_between,
+                comments_after_else,
+                else_branch,
+                ..
+            } => {
+                if BraceInserter::needs_braces(then_branch) {
+                    let then_comments = BraceInserter::take_same_line_comments(comments_between);
+                    BraceInserter::wrap_with(
+                        then_branch,
+                        || BraceInserter::take_trailing_comments(condition),
+                        then_comments,
+                    );
+                }
+
+                match else_branch {
+                    Some(else_branch) if matches!(else_branch.statement, Statement::If { .. }) => {
+                        BraceInserter::wrap_bodies(else_branch, same_line_comments)
+                    }
+                    Some(else_branch) if BraceInserter::needs_braces(else_branch) => {
+                        BraceInserter::wrap_with(
+                            else_branch,
+                            || BraceInserter::take_comments(comments_after_else),
+                            same_line_comments,
+                        );
+                        Vec::new()
+                    }
+                    _ => same_line_comments,
+                }
+            }
+            Statement::WhileWithRepeat { condition, body, .. } if BraceInserter::needs_braces(body) => {
+                BraceInserter::wrap_with(
+                    body,
+                    || BraceInserter::take_trailing_comments(condition),
+                    same_line_comments,
+                );
+                Vec::new()
+            }
+            Statement::DoUntil {
+                comments_after_control_word,
+                body,
+                comments_between,
+                ..
+            } if BraceInserter::needs_braces(body) => {
+                let body_comments = BraceInserter::take_same_line_comments(comments_between);
+                BraceInserter::wrap_with(
+                    body,
+                    || BraceInserter::take_comments(comments_after_control_word),
+                    body_comments,
+                );
+                same_line_comments
+            }
+            Statement::For {
+                comments_after_rparen,
+                body,
+                ..
+            } if BraceInserter::needs_braces(body) => {
+                BraceInserter::wrap_with(
+                    body,
+                    || BraceInserter::take_comments(comments_after_rparen),
+                    same_line_comments,
+                );
+                Vec::new()
+            }
+            _ => same_line_comments,
+        }
+    }
+
+    // The body's own bodies are wrapped first, so comments after it reach the innermost
+    // statement they were written behind.
+    fn wrap_with<'a>(
+        body: &mut StmtBox<'a>,
+        comments: impl FnOnce() -> Vec<Token<'a>>,
+        same_line_comments: Vec<Token<'a>>,
+    ) {
+        let same_line_comments = BraceInserter::wrap_bodies(body, same_line_comments);
+        BraceInserter::wrap(body, comments(), same_line_comments);
+    }
+
+    // Comments, stray newlines and directives aren't really bodies, so we don't wrap them.
+    fn needs_braces(body: &StatementWrapper<'_>) -> bool {
+        match &body.statement {
+            Statement::Block { .. }
+            | Statement::Comment { .. }
+            | Statement::MultilineComment { .. }
+            | Statement::RegionBegin(_)
+            | Statement::RegionEnd(_)
+            | Statement::Macro { .. }
+            | Statement::Define { .. } => false,
+            Statement::ExpressionStatement { expression } => {
+                matches!(
+                    expression.expr,
+                    Expr::Newline | Expr::Comment { .. } | Expr::MultilineComment { .. }
+                ) == false
+            }
+            _ => true,
+        }
+    }
+
+    // We build the same tree the parser gives us for `{\n body\n}`, so the block prints
+    // over several lines. The comments which sat between the control statement and its
+    // body move inside the new block, as do comments on the same line as the body.
+    fn wrap<'a>(body: &mut StmtBox<'a>, mut comments: Vec<Token<'a>>, same_line_comments: Vec<Token<'a>>) {
+        let has_newline = comments
+            .iter()
+            .any(|token| matches!(token.token_type, TokenType::Newline(_)));
+        if has_newline == false {
+            comments.push(Token::new(TokenType::Newline(0), 0, 0));
+        }
+
+        let placeholder = StatementWrapper::new(Statement::Break, false);
+        let mut statements = vec![std::mem::replace(body, placeholder)];
+        for comment in same_line_comments {
+            let statement = match comment.token_type {
+                TokenType::MultilineComment(_) => Statement::MultilineComment {
+                    multiline_comment: comment,
+                },
+                _ => Statement::Comment { comment },
+            };
+            statements.push(StatementWrapper::new(statement, false));
+        }
+        statements.push(StatementWrapper::new(
+            Statement::ExpressionStatement {
+                expression: Box::new(ExprBoxInterior {
+                    expr: Expr::Newline,
+                    trailing_comments: None,
+                    span: Span::default(),
+                }),
+            },
+            false,
+        ));
+
+        *body = StatementWrapper::new(
+            Statement::Block {
+                comments_after_lbrace: Some(comments),
+                statements,
+            },
+            false,
+        );
+    }
+
+    // Walks down the right edge of a condition, collecting every comment printed after it.
+    fn take_trailing_comments<'a>(expr: &mut ExprBoxInterior<'a>) -> Vec<Token<'a>> {
+        let mut taken = match &mut expr.expr {
+            Expr::Grouping {
+                comments_and_newlines_after_rparen,
+                ..
+            } => BraceInserter::take_comments(comments_and_newlines_after_rparen),
+            Expr::Literal { comments, .. }
+            | Expr::NumberStartDot { comments, .. }
+            | Expr::NumberEndDot { comments, .. }
+            | Expr::Identifier { comments, .. } => BraceInserter::take_comments(comments),
+            Expr::Postfix {
+                comments_and_newlines_between,
+                ..
+            } => BraceInserter::take_comments(comments_and_newlines_between),
+            Expr::Binary { right, .. }
+            | Expr::Assign { right, .. }
+            | Expr::Unary { right, .. }
+            | Expr::Ternary { right, .. } => BraceInserter::take_trailing_comments(right),
+            Expr::DotAccess { instance_variable, .. } => BraceInserter::take_trailing_comments(instance_variable),
+            _ => Vec::new(),
+        };
+
+        taken.append(&mut BraceInserter::take_comments(&mut expr.trailing_comments));
+        taken
+    }
+
+    fn take_same_line_comments<'a>(comments: &mut CommentsAndNewlines<'a>) -> Vec<Token<'a>> {
+        match comments {
+            Some(vec) => {
+                let end = vec
+                    .iter()
+                    .position(|token| {
+                        matches!(token.token_type, TokenType::Comment(_) | TokenType::MultilineComment(_)) == false
+                    })
+                    .unwrap_or(vec.len());
+                vec.drain(..end).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    // A `then` has to stay ahead of the brace, so we only take what comes after it.
+    fn take_comments<'a>(comments: &mut CommentsAndNewlines<'a>) -> Vec<Token<'a>> {
+        match comments {
+            Some(vec) => {
+                let keep = vec
+                    .iter()
+                    .rposition(|token| token.token_type == TokenType::Then)
+                    .map_or(0, |then| then + 1);
+                vec.split_off(keep)
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+// Bodies are wrapped on the way down, before the statements inside them are walked.
+impl<'a> VisitorMut<'a> for BraceInserter {
+    fn visit_statement_mut(&mut self, stmt: &mut StatementWrapper<'a>) {
+        match &mut stmt.statement {
+            Statement::Block { statements, .. } | Statement::Define { body: statements, .. } => {
+                BraceInserter::wrap_statements(statements);
+            }
+            Statement::Switch { cases, .. } => {
+                for case in cases {
+                    BraceInserter::wrap_statements(&mut case.statements);
+                }
+            }
+            _ => {
+                BraceInserter::wrap_bodies(stmt, Vec::new());
+            }
+        }
+
+        walk_statement_mut(self, stmt);
+    }
+
+    // Only the function bodies in an expression matter here, so an operator chain is stepped
+    // through without recursing once per operator.
+    fn visit_expr_mut(&mut self, expr: &mut ExprBoxInterior<'a>) {
+        let mut expr = expr;
+        while let Expr::Binary { .. } = expr.expr {
+            match &mut expr.expr {
+                Expr::Binary { left, right, .. } => {
+                    self.visit_expr_mut(right);
+                    expr = left;
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        walk_expr_mut(self, expr);
+    }
+}
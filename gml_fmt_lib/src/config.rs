@@ -1,49 +1,88 @@
+use super::glob::Glob;
+use super::project::{Project, ProjectError};
 use bitflags;
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::{ffi::OsStr, fs};
 
+const IGNORE_FILE_NAMES: [&str; 2] = [".gitignore", ".gml_fmtignore"];
+
 pub struct Config {
     pub files: Vec<PathBuf>,
     pub print_flags: PrintFlags,
+    /// The folder the files were found in, which is where backups are kept.
+    pub root: PathBuf,
+    /// Things we skipped while looking for files, like folders we couldn't read.
+    pub warnings: Vec<String>,
+}
+
+// One line of a `.gitignore` or `.gml_fmtignore`.
+struct IgnoreRule {
+    folder: PathBuf,
+    glob: Glob,
+    negated: bool,
+    only_folders: bool,
+}
+
+// Everything which decides whether we take a file while walking a folder.
+struct Walk<'a> {
+    root: PathBuf,
+    input_path: &'a Path,
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+    ignore_rules: Vec<IgnoreRule>,
+    visited: HashSet<PathBuf>,
 }
 
 impl Config {
     pub fn new(input_path: PathBuf, print_flags: PrintFlags, do_file: bool) -> Result<Config, &'static str> {
-        let mut config = Config {
-            files: Vec::new(),
-            print_flags,
-        };
+        Config::with_filters(input_path, print_flags, do_file, &[], &[])
+    }
 
+    /// When walking a folder, only files matching one of `include` (if there are any) are
+    /// taken, and files and folders matching one of `exclude` are skipped. The globs are
+    /// matched from the folder we walk. We also skip hidden folders, and whatever the
+    /// `.gitignore` and `.gml_fmtignore` files on the way ignore.
+    pub fn with_filters(
+        input_path: PathBuf,
+        print_flags: PrintFlags,
+        do_file: bool,
+        include: &[String],
+        exclude: &[String],
+    ) -> Result<Config, &'static str> {
         if input_path.exists() == false {
             return Err("Filepath given does not exist.");
         }
 
+        let mut root = fs::canonicalize(&input_path).map_err(|_| "Could not read the path given.")?;
+        if root.is_file() {
+            root.pop();
+        }
+        let mut config = Config {
+            files: Vec::new(),
+            print_flags,
+            root,
+            warnings: Vec::new(),
+        };
+
         match (input_path.is_dir(), do_file) {
             (true, true) => {
                 return Err("Passed -f or --file but gave a directory filepath.");
             }
 
             (true, false) => {
-                fn take_in_gml_files(directory_path: &PathBuf, config: &mut Config) {
-                    let gml_name = OsStr::new("gml");
-
-                    for entry in
-                        fs::read_dir(directory_path).expect(&format!("Error reading directory {:?}.", directory_path))
-                    {
-                        let entry = entry.expect(&format!("Error reading file"));
-                        let path = entry.path();
-
-                        if path.is_dir() == false {
-                            if path.extension() == Some(gml_name) {
-                                config.load_file_path(path);
-                            }
-                        } else {
-                            take_in_gml_files(&path, config);
-                        }
-                    }
-                }
+                let mut walk = Walk {
+                    include: include.iter().map(|glob| Glob::in_folder(glob)).collect(),
+                    exclude: exclude.iter().map(|glob| Glob::in_folder(glob)).collect(),
+                    ignore_rules: Vec::new(),
+                    visited: HashSet::new(),
+                    input_path: &input_path,
+                    root: config.root.clone(),
+                };
 
-                take_in_gml_files(&input_path, &mut config);
+                walk.read_outer_ignore_files(&mut config);
+                let root = walk.root.clone();
+                walk.take_in_gml_files(&root, &mut config);
             }
 
             (false, true) => {
@@ -58,15 +97,213 @@ impl Config {
         Ok(config)
     }
 
+    /// Takes the scripts and object events of `project`, or only those of the resources
+    /// called `resources`, if any are named. `include` and `exclude` work as they do when
+    /// walking, but are matched from the project's folder.
+    pub fn from_project(
+        project: &Project,
+        print_flags: PrintFlags,
+        resources: &[String],
+        include: &[String],
+        exclude: &[String],
+    ) -> Result<Config, ProjectError> {
+        let include: Vec<Glob> = include.iter().map(|glob| Glob::in_folder(glob)).collect();
+        let exclude: Vec<Glob> = exclude.iter().map(|glob| Glob::in_folder(glob)).collect();
+        let matches = |globs: &[Glob], path: &Path| globs.iter().any(|glob| glob.covers(&project.folder, path));
+
+        let mut config = Config {
+            files: Vec::new(),
+            print_flags,
+            root: fs::canonicalize(&project.folder).unwrap_or_else(|_| project.folder.clone()),
+            warnings: project.warnings.clone(),
+        };
+
+        for path in project.files(resources)? {
+            let is_included = include.is_empty() || matches(&include, &path);
+            if is_included && matches(&exclude, &path) == false {
+                config.load_file_path(path);
+            }
+        }
+
+        Ok(config)
+    }
+
     pub fn load_file_path(&mut self, path: PathBuf) {
         self.files.push(path);
     }
 }
 
+impl<'a> Walk<'a> {
+    fn take_in_gml_files(&mut self, directory_path: &Path, config: &mut Config) {
+        // Symlinks can lead back to a folder we're already in.
+        match fs::canonicalize(directory_path) {
+            Ok(canonical_path) => {
+                if self.visited.insert(canonical_path) == false {
+                    config.warnings.push(format!(
+                        "Skipping {:?}, which links to a folder we have already visited.",
+                        self.display_path(directory_path)
+                    ));
+                    return;
+                }
+            }
+            Err(e) => {
+                config.warnings.push(format!(
+                    "Error reading directory {:?}: {}",
+                    self.display_path(directory_path),
+                    e
+                ));
+                return;
+            }
+        }
+
+        let rule_count = self.ignore_rules.len();
+        self.read_ignore_files(directory_path, config);
+
+        let mut entries = Vec::new();
+        match fs::read_dir(directory_path) {
+            Ok(read_dir) => {
+                for entry in read_dir {
+                    match entry {
+                        Ok(entry) => entries.push(entry.path()),
+                        Err(e) => config.warnings.push(format!(
+                            "Error reading an entry in {:?}: {}",
+                            self.display_path(directory_path),
+                            e
+                        )),
+                    }
+                }
+            }
+            Err(e) => config.warnings.push(format!(
+                "Error reading directory {:?}: {}",
+                self.display_path(directory_path),
+                e
+            )),
+        }
+        entries.sort();
+
+        let gml_name = OsStr::new("gml");
+        for path in entries {
+            if path.is_dir() {
+                let is_hidden = matches!(path.file_name(), Some(name) if name.to_string_lossy().starts_with('.'));
+
+                if is_hidden == false && self.is_ignored(&path, true) == false {
+                    self.take_in_gml_files(&path, config);
+                }
+            } else if path.extension() == Some(gml_name) && self.is_ignored(&path, false) == false {
+                let is_included =
+                    self.include.is_empty() || self.include.iter().any(|glob| glob.covers(&self.root, &path));
+
+                if is_included {
+                    config.load_file_path(self.display_path(&path));
+                }
+            }
+        }
+
+        self.ignore_rules.truncate(rule_count);
+    }
+
+    // The later a rule comes, the more it matters, so nearer ignore files win.
+    fn is_ignored(&self, path: &Path, is_folder: bool) -> bool {
+        if self.exclude.iter().any(|glob| glob.is_match_in(&self.root, path)) {
+            return true;
+        }
+
+        let mut is_ignored = false;
+        for rule in &self.ignore_rules {
+            if rule.only_folders && is_folder == false {
+                continue;
+            }
+
+            if rule.glob.is_match_in(&rule.folder, path) {
+                is_ignored = rule.negated == false;
+            }
+        }
+
+        is_ignored
+    }
+
+    // Ignore files above the folder we walk still count, as far up as the repository or project folder.
+    fn read_outer_ignore_files(&mut self, config: &mut Config) {
+        let is_top = |directory: &Path| directory.join(".git").exists() || Project::is_project_folder(directory);
+        if is_top(&self.root) {
+            return;
+        }
+
+        let outer_folders: Vec<PathBuf> = self.root.ancestors().skip(1).map(Path::to_owned).collect();
+        if let Some(top) = outer_folders.iter().position(|directory| is_top(directory)) {
+            for directory in outer_folders[..=top].iter().rev() {
+                self.read_ignore_files(directory, config);
+            }
+        }
+    }
+
+    fn read_ignore_files(&mut self, directory: &Path, config: &mut Config) {
+        for name in &IGNORE_FILE_NAMES {
+            let ignore_path = directory.join(name);
+            if ignore_path.is_file() == false {
+                continue;
+            }
+
+            match fs::read_to_string(&ignore_path) {
+                Ok(contents) => {
+                    let rules = contents.lines().filter_map(|line| IgnoreRule::parse(line, directory));
+                    self.ignore_rules.extend(rules);
+                }
+                Err(e) => config
+                    .warnings
+                    .push(format!("Error reading {:?}: {}", self.display_path(&ignore_path), e)),
+            }
+        }
+    }
+
+    // We walk the canonical folder, but show paths the way they were given to us.
+    fn display_path(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.root) {
+            Ok(relative_path) => self.input_path.join(relative_path),
+            Err(_) => path.to_owned(),
+        }
+    }
+}
+
+impl IgnoreRule {
+    // Follows `.gitignore`: `#` starts a comment, `!` brings back what an earlier rule
+    // ignored, and a trailing `/` only matches folders.
+    fn parse(line: &str, folder: &Path) -> Option<IgnoreRule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let line = line.strip_prefix('\\').unwrap_or(line);
+
+        let (only_folders, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            return None;
+        }
+
+        Some(IgnoreRule {
+            folder: folder.to_owned(),
+            glob: Glob::in_folder(line),
+            negated,
+            only_folders,
+        })
+    }
+}
+
 bitflags::bitflags! {
     pub struct PrintFlags: u8 {
-        const OVERWRITE = 0b001;
-        const LOGS      = 0b010;
-        const LOG_AST   = 0b100;
+        const OVERWRITE         = 0b000001;
+        const LOGS              = 0b000010;
+        const LOG_AST           = 0b000100;
+        const BACKUP            = 0b001000;
+        const VERIFY            = 0b010000;
+        const CHECK_IDEMPOTENCE = 0b100000;
     }
 }
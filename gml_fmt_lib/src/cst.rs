@@ -0,0 +1,197 @@
+use super::error::FormatError;
+use super::expressions::*;
+use super::lex_token::{Span, Token, TokenType};
+use super::parser::Parser;
+use super::scanner::Scanner;
+use super::statements::*;
+use super::visitor::{walk_ast, walk_expr, walk_statement, Visitor};
+use std::fmt;
+
+/// A node of the concrete syntax tree, which keeps every byte of the source, so printing
+/// it gives back exactly what was parsed. Its nodes are the statements and expressions
+/// of the AST, at the same spans, and its leaves are tokens and the whitespace between
+/// them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode<'a> {
+    pub kind: SyntaxKind,
+    pub span: Span,
+    pub children: Vec<SyntaxElement<'a>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SyntaxKind {
+    /// The whole source.
+    Root,
+    Statement,
+    Expression,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement<'a> {
+    Node(SyntaxNode<'a>),
+    Token(SyntaxToken<'a>),
+}
+
+/// A leaf of the tree, with its text exactly as it is in the source.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SyntaxToken<'a> {
+    /// `None` for the whitespace the scanner skips between tokens, such as spaces, the
+    /// `\r` of a `\r\n` and byte order marks.
+    pub token: Option<Token<'a>>,
+    pub text: &'a str,
+    pub span: Span,
+}
+
+impl<'a> SyntaxToken<'a> {
+    /// Whitespace, newlines and comments, which the code means the same without.
+    pub fn is_trivia(&self) -> bool {
+        match self.token {
+            Some(token) => matches!(
+                token.token_type,
+                TokenType::Newline(_) | TokenType::Comment(_) | TokenType::MultilineComment(_)
+            ),
+            None => true,
+        }
+    }
+}
+
+impl<'a> SyntaxNode<'a> {
+    /// Every token under this node, in the order they are in the source.
+    pub fn tokens(&self) -> Vec<&SyntaxToken<'a>> {
+        let mut tokens = Vec::new();
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'n>(&'n self, tokens: &mut Vec<&'n SyntaxToken<'a>>) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.collect_tokens(tokens),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+    }
+}
+
+/// Prints the source back, byte for byte.
+impl fmt::Display for SyntaxNode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for token in self.tokens() {
+            f.write_str(token.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds the concrete syntax tree of `source`. It fails where parsing the AST would.
+pub fn build_cst(source: &str) -> Result<SyntaxNode<'_>, FormatError> {
+    let ast = Parser::new(source)
+        .build_ast()
+        .map_err(|e| FormatError::parse(source, e))?;
+
+    let mut spans = NodeSpans(Vec::new());
+    walk_ast(&mut spans, &ast);
+    // Parents come before their children, and a statement before an expression with the
+    // same span, since the walk visits it first and the sort is stable.
+    spans
+        .0
+        .sort_by_key(|(span, _)| (span.start, std::cmp::Reverse(span.end)));
+
+    let mut stack = vec![SyntaxNode {
+        kind: SyntaxKind::Root,
+        span: Span {
+            start: 0,
+            end: source.len(),
+        },
+        children: Vec::new(),
+    }];
+    let mut nodes = spans.0.into_iter().peekable();
+
+    for token in lossless_tokens(source) {
+        while stack.len() > 1 && stack[stack.len() - 1].span.end <= token.span.start {
+            close_node(&mut stack);
+        }
+
+        // Nodes which don't start and end on token boundaries, such as the expressions
+        // inside a `#macro`, which is a single token, are left out.
+        while let Some((span, kind)) = nodes.peek().copied() {
+            if span.start > token.span.start {
+                break;
+            }
+            nodes.next();
+
+            let parent_end = stack[stack.len() - 1].span.end;
+            if span.start == token.span.start && span.end <= parent_end {
+                stack.push(SyntaxNode {
+                    kind,
+                    span,
+                    children: Vec::new(),
+                });
+            }
+        }
+
+        let last = stack.len() - 1;
+        stack[last].children.push(SyntaxElement::Token(token));
+    }
+
+    while stack.len() > 1 {
+        close_node(&mut stack);
+    }
+    Ok(stack.remove(0))
+}
+
+fn close_node(stack: &mut Vec<SyntaxNode<'_>>) {
+    if let Some(node) = stack.pop() {
+        let last = stack.len() - 1;
+        stack[last].children.push(SyntaxElement::Node(node));
+    }
+}
+
+// The scanner's tokens, with the whitespace it skips between them filled in.
+fn lossless_tokens(source: &str) -> Vec<SyntaxToken<'_>> {
+    let mut tokens = Vec::new();
+    let mut position = 0;
+
+    for token in Scanner::new(source) {
+        tokens.extend(whitespace(source, position, token.span.start));
+        tokens.push(SyntaxToken {
+            token: Some(token),
+            text: &source[token.span.start..token.span.end],
+            span: token.span,
+        });
+        position = token.span.end;
+    }
+    tokens.extend(whitespace(source, position, source.len()));
+
+    tokens
+}
+
+fn whitespace(source: &str, start: usize, end: usize) -> Option<SyntaxToken<'_>> {
+    if start < end {
+        Some(SyntaxToken {
+            token: None,
+            text: &source[start..end],
+            span: Span { start, end },
+        })
+    } else {
+        None
+    }
+}
+
+struct NodeSpans(Vec<(Span, SyntaxKind)>);
+
+impl<'a> Visitor<'a> for NodeSpans {
+    fn visit_statement(&mut self, stmt: &StatementWrapper<'a>) {
+        if stmt.span.start < stmt.span.end {
+            self.0.push((stmt.span, SyntaxKind::Statement));
+        }
+        walk_statement(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &ExprBoxInterior<'a>) {
+        if expr.span.start < expr.span.end {
+            self.0.push((expr.span, SyntaxKind::Expression));
+        }
+        walk_expr(self, expr);
+    }
+}
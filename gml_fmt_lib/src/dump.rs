@@ -0,0 +1,35 @@
+use super::error::FormatError;
+use super::parser::Parser;
+use super::scanner::Scanner;
+use serde::Serialize;
+use std::fmt::Debug;
+
+/// How `dump_ast` and `dump_tokens` write out what they find.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DumpFormat {
+    /// Pretty printed JSON, for scripts. Every token has its line, column and byte span.
+    Json,
+    /// Rust's debug output, as `-s` prints.
+    Debug,
+}
+
+/// Parses `source` and writes out its AST, without changing it as formatting would.
+pub fn dump_ast(source: &str, format: DumpFormat) -> Result<String, FormatError> {
+    let ast = Parser::new(source)
+        .build_ast()
+        .map_err(|e| FormatError::parse(source, e))?;
+    write(&ast, format)
+}
+
+/// Writes out every token in `source`, including the newlines and comments.
+pub fn dump_tokens(source: &str, format: DumpFormat) -> Result<String, FormatError> {
+    let tokens: Vec<_> = Scanner::new(source).collect();
+    write(&tokens, format)
+}
+
+fn write<T: Serialize + Debug>(value: &T, format: DumpFormat) -> Result<String, FormatError> {
+    match format {
+        DumpFormat::Json => serde_json::to_string_pretty(value).map_err(FormatError::Json),
+        DumpFormat::Debug => Ok(format!("{:#?}", value)),
+    }
+}
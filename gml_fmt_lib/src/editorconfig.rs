@@ -0,0 +1,106 @@
+use super::glob::Glob;
+use super::lang_config::{ConfigError, ConfigErrorKind};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const EDITORCONFIG_NAME: &str = ".editorconfig";
+
+/// The EditorConfig properties which apply to one file, each with the `.editorconfig`
+/// it came from. Names and values are lowercased, since EditorConfig doesn't care about case.
+pub(crate) struct EditorConfig {
+    properties: BTreeMap<String, (String, PathBuf)>,
+}
+
+// One `.editorconfig` file, as written.
+struct EditorConfigFile {
+    is_root: bool,
+    sections: Vec<(Glob, Vec<(String, String)>)>,
+}
+
+impl EditorConfig {
+    /// Walks up from `path` until a `.editorconfig` says `root = true`. Nearer files win
+    /// over further ones, and later sections win over earlier ones in the same file.
+    /// `path` should be canonical. A folder is treated as if it were a `.gml` file inside it.
+    pub fn for_file(path: &Path) -> Result<EditorConfig, ConfigError> {
+        let file_path = if path.is_dir() {
+            path.join("_.gml")
+        } else {
+            path.to_owned()
+        };
+
+        let mut files = Vec::new();
+        for directory in file_path.ancestors().skip(1) {
+            let editorconfig_path = directory.join(EDITORCONFIG_NAME);
+            if editorconfig_path.is_file() == false {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&editorconfig_path)
+                .map_err(|e| ConfigError::new(Some(&editorconfig_path), ConfigErrorKind::Io(e)))?;
+            let file = EditorConfigFile::parse(&contents);
+            let is_root = file.is_root;
+            files.push((directory, editorconfig_path, file));
+
+            if is_root {
+                break;
+            }
+        }
+
+        let mut properties = BTreeMap::new();
+        for (directory, editorconfig_path, file) in files.into_iter().rev() {
+            for (glob, section) in &file.sections {
+                if glob.is_match_in(directory, &file_path) {
+                    for (key, value) in section {
+                        properties.insert(key.clone(), (value.clone(), editorconfig_path.clone()));
+                    }
+                }
+            }
+        }
+
+        Ok(EditorConfig { properties })
+    }
+
+    pub fn get(&self, key: &str) -> Option<(&str, &Path)> {
+        self.properties
+            .get(key)
+            .map(|(value, source)| (value.as_str(), source.as_path()))
+    }
+}
+
+impl EditorConfigFile {
+    // EditorConfig asks us to pass over lines we don't understand, so this never fails.
+    fn parse(contents: &str) -> EditorConfigFile {
+        let mut file = EditorConfigFile {
+            is_root: false,
+            sections: Vec::new(),
+        };
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                let pattern = &line[1..line.len() - 1];
+                file.sections.push((Glob::in_folder(pattern), Vec::new()));
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key.trim().to_lowercase(), value.trim().to_lowercase()),
+                _ => continue,
+            };
+
+            match file.sections.last_mut() {
+                Some((_, section)) => section.push((key, value)),
+                None if key == "root" => file.is_root = value == "true",
+                None => {}
+            }
+        }
+
+        file
+    }
+}
@@ -0,0 +1,113 @@
+// What Windows-1252 puts at 0x80 to 0x9F. The five bytes it leaves undefined are read
+// as the control characters Latin-1 has there, so every byte has a character and
+// writing a file back gives the same bytes we read.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}', '\u{2c6}', '\u{2030}',
+    '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}', '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}',
+    '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}', '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}',
+    '\u{17e}', '\u{178}',
+];
+
+/// How a source file was stored, so we can write it back the same way.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// What GM8 and GMS1 projects made on Windows are usually in. It is a superset of
+    /// Latin-1's printable characters, so Latin-1 files are read as this too.
+    Windows1252,
+}
+
+impl Encoding {
+    /// Reads `bytes` as UTF-8 if they are valid UTF-8, as UTF-16 if they start with its
+    /// byte order mark, and as Windows-1252 otherwise. UTF-16 byte order marks are kept
+    /// as a `\u{feff}` at the start of the text. Text with NUL characters in it isn't
+    /// GML, so we give up on it.
+    pub fn decode(bytes: &[u8]) -> Result<(String, Encoding), &'static str> {
+        let (text, encoding) = match bytes {
+            [0xff, 0xfe, ..] => (Encoding::decode_utf16(bytes, u16::from_le_bytes)?, Encoding::Utf16Le),
+            [0xfe, 0xff, ..] => (Encoding::decode_utf16(bytes, u16::from_be_bytes)?, Encoding::Utf16Be),
+            _ => match std::str::from_utf8(bytes) {
+                Ok(text) => (text.to_owned(), Encoding::Utf8),
+                Err(_) => (
+                    bytes.iter().map(|byte| Encoding::windows_1252_char(*byte)).collect(),
+                    Encoding::Windows1252,
+                ),
+            },
+        };
+
+        if text.contains('\0') {
+            return Err("it looks like a binary file.");
+        }
+        Ok((text, encoding))
+    }
+
+    /// Writes `text` back in this encoding. Fails if a character can't be written in it,
+    /// which only Windows-1252 can do.
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, char> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Utf16Le => Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+            Encoding::Utf16Be => Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+            Encoding::Windows1252 => text.chars().map(Encoding::windows_1252_byte).collect(),
+        }
+    }
+
+    fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16) -> Result<String, &'static str> {
+        if bytes.len() % 2 == 1 {
+            return Err("it starts with a UTF-16 byte order mark, but has an odd number of bytes.");
+        }
+
+        let units = bytes.chunks(2).map(|pair| to_unit([pair[0], pair[1]]));
+        std::char::decode_utf16(units)
+            .collect::<Result<String, _>>()
+            .map_err(|_| "it starts with a UTF-16 byte order mark, but is not valid UTF-16.")
+    }
+
+    fn windows_1252_char(byte: u8) -> char {
+        match byte {
+            0x80..=0x9f => WINDOWS_1252_HIGH[(byte - 0x80) as usize],
+            _ => byte as char,
+        }
+    }
+
+    fn windows_1252_byte(c: char) -> Result<u8, char> {
+        match c as u32 {
+            code @ 0..=0x7f | code @ 0xa0..=0xff => Ok(code as u8),
+            _ => match WINDOWS_1252_HIGH.iter().position(|high| *high == c) {
+                Some(position) => Ok(0x80 + position as u8),
+                None => Err(c),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod encoding_test {
+    use super::Encoding;
+
+    #[test]
+    fn round_trips() {
+        let windows_1252 = b"// caf\xe9 \x80\x81\x9f\nvar a = 1;\n";
+        let (text, encoding) = Encoding::decode(windows_1252).unwrap();
+        assert_eq!(encoding, Encoding::Windows1252);
+        assert_eq!(text, "// caf\u{e9} \u{20ac}\u{81}\u{178}\nvar a = 1;\n");
+        assert_eq!(encoding.encode(&text).unwrap(), windows_1252.to_vec());
+
+        let utf16 = b"\xff\xfea\x00\xe9\x00";
+        let (text, encoding) = Encoding::decode(utf16).unwrap();
+        assert_eq!(encoding, Encoding::Utf16Le);
+        assert_eq!(text, "\u{feff}a\u{e9}");
+        assert_eq!(encoding.encode(&text).unwrap(), utf16.to_vec());
+
+        assert_eq!(Encoding::decode("é".as_bytes()).unwrap().1, Encoding::Utf8);
+        assert_eq!(Encoding::Windows1252.encode("\u{3b1}"), Err('\u{3b1}'));
+    }
+
+    #[test]
+    fn rejects_binary() {
+        assert!(Encoding::decode(b"\x89PNG\r\n\x1a\n\x00\x00").is_err());
+        assert!(Encoding::decode(b"\xfe\xff\x00").is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use super::lang_config::ConfigError;
+use super::lex_token::Span;
+use super::line_index::LineIndex;
+use super::parser::ParseError;
+use std::path::PathBuf;
+use std::{error::Error, fmt, io};
+
+/// Why `format_str` or `format_file` couldn't format something.
+#[derive(Debug)]
+pub enum FormatError {
+    /// The source isn't GML we know how to parse. `line` and `column` count from 1, with the
+    /// column in bytes, and `span` is what the parser stopped at.
+    Parse {
+        message: String,
+        line: u32,
+        column: u32,
+        span: Span,
+    },
+    /// A file couldn't be read, or isn't text.
+    Io { path: PathBuf, error: io::Error },
+    /// The config for a file couldn't be read or used.
+    Config(ConfigError),
+    /// An AST or its tokens couldn't be written as JSON.
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Parse {
+                message, line, column, ..
+            } => write!(f, "{}:{}: could not parse: {}", line, column, message),
+            FormatError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            FormatError::Config(e) => write!(f, "{}", e),
+            FormatError::Json(e) => write!(f, "could not write JSON: {}", e),
+        }
+    }
+}
+
+impl Error for FormatError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FormatError::Parse { .. } => None,
+            FormatError::Io { error, .. } => Some(error),
+            FormatError::Config(e) => Some(e),
+            FormatError::Json(e) => Some(e),
+        }
+    }
+}
+
+impl FormatError {
+    // Finds the line and column of what `Parser::build_ast` stopped at in `source`.
+    pub(crate) fn parse(source: &str, error: anyhow::Error) -> FormatError {
+        let ParseError { message, span } = error.downcast().unwrap_or_else(|e: anyhow::Error| ParseError {
+            message: e.to_string(),
+            span: Span::default(),
+        });
+        let position = LineIndex::new(source).line_col(span.start);
+
+        FormatError::Parse {
+            message,
+            line: position.line + 1,
+            column: position.column + 1,
+            span,
+        }
+    }
+}
+
+impl From<ConfigError> for FormatError {
+    fn from(e: ConfigError) -> FormatError {
+        FormatError::Config(e)
+    }
+}
@@ -1,16 +1,22 @@
 use super::lex_token::*;
 use super::statements::DelimitedLines;
+use serde::Serialize;
 pub type ExprBox<'a> = Box<ExprBoxInterior<'a>>;
+/// The comments, newlines and other tokens we keep, but don't parse, between two parts
+/// of a statement or expression.
 pub type CommentsAndNewlines<'a> = Option<Vec<Token<'a>>>;
 pub type DSAccess<'a> = Vec<(CommentsAndNewlines<'a>, ExprBox<'a>)>;
 
-#[derive(Debug)]
+/// An expression, and the comments which follow it. Its span runs from its first token to
+/// its last, leaving out the comments and newlines after it.
+#[derive(Debug, Serialize)]
 pub struct ExprBoxInterior<'a> {
     pub expr: Expr<'a>,
     pub trailing_comments: CommentsAndNewlines<'a>,
+    pub span: Span,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub enum Expr<'a> {
     Call {
         procedure_name: ExprBox<'a>,
@@ -104,3 +110,29 @@ pub enum Expr<'a> {
         literal_token: Token<'a>,
     },
 }
+
+// A chain like `a + b + c` is a tree as deep as the chain is long, with the first link at
+// the bottom. Dropping it link by link would recurse all the way down, so the links below
+// this one are unhooked and dropped in a loop.
+impl Drop for ExprBoxInterior<'_> {
+    fn drop(&mut self) {
+        fn unhook_chain<'a>(expr: &mut ExprBoxInterior<'a>) -> Option<ExprBox<'a>> {
+            match &mut expr.expr {
+                Expr::Binary { left, .. } if matches!(left.expr, Expr::Binary { .. }) => {
+                    let placeholder = Box::new(ExprBoxInterior {
+                        expr: Expr::Newline,
+                        trailing_comments: None,
+                        span: Span::default(),
+                    });
+                    Some(std::mem::replace(left, placeholder))
+                }
+                _ => None,
+            }
+        }
+
+        let mut below = unhook_chain(self);
+        while let Some(mut link) = below {
+            below = unhook_chain(&mut link);
+        }
+    }
+}
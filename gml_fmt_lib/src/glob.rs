@@ -0,0 +1,218 @@
+use std::path::Path;
+
+/// A shell-style pattern for matching paths, as used by `.editorconfig` sections and
+/// config overrides.
+///
+/// `*` matches anything but a `/`, `**` matches anything at all, `?` matches one
+/// character, `[abc]`, `[a-z]` and `[!abc]` match one character from (or not from) a
+/// set, and `{a,b}` matches any one of its alternatives. A `\` makes the next character
+/// match only itself. Paths are matched with `/` between their components.
+#[derive(Debug, Clone)]
+pub(crate) struct Glob {
+    alternatives: Vec<Vec<char>>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Glob {
+        Glob {
+            alternatives: Glob::expand_braces(pattern.chars().collect()),
+        }
+    }
+
+    /// A pattern for the paths in a folder. Without a `/`, it matches files of that name
+    /// in any folder beneath, and with one, it is matched from the folder itself.
+    pub fn in_folder(pattern: &str) -> Glob {
+        if pattern.contains('/') {
+            Glob::new(pattern.trim_start_matches('/'))
+        } else {
+            Glob::new(&format!("**/{}", pattern))
+        }
+    }
+
+    /// Matches `path` by where it is in `folder`. Paths outside of `folder` never match.
+    pub fn is_match_in(&self, folder: &Path, path: &Path) -> bool {
+        match path.strip_prefix(folder) {
+            Ok(relative_path) => {
+                let relative_path: Vec<_> = relative_path
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect();
+                self.is_match(&relative_path.join("/"))
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Like `is_match_in`, but a match on one of the folders `path` is in counts too,
+    /// as it does in a `.gitignore`. So `datafiles` covers `datafiles/a/b.gml`.
+    pub fn covers(&self, folder: &Path, path: &Path) -> bool {
+        path.ancestors()
+            .take_while(|ancestor| *ancestor != folder)
+            .any(|ancestor| self.is_match_in(folder, ancestor))
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        let path: Vec<char> = path.chars().collect();
+        self.alternatives.iter().any(|pattern| Glob::match_here(pattern, &path))
+    }
+
+    // `{a,b}c` becomes `ac` and `bc`, one brace at a time, so nested braces work too.
+    // Braces without a comma in them are just characters.
+    fn expand_braces(pattern: Vec<char>) -> Vec<Vec<char>> {
+        let mut open = None;
+        let mut depth = 0;
+        let mut commas = Vec::new();
+        let mut i = 0;
+
+        while i < pattern.len() {
+            match pattern[i] {
+                '\\' => i += 1,
+                '{' => {
+                    if depth == 0 {
+                        open = Some(i);
+                        commas.clear();
+                    }
+                    depth += 1;
+                }
+                ',' if depth == 1 => commas.push(i),
+                '}' if depth > 0 => {
+                    depth -= 1;
+                    if depth == 0 {
+                        if let Some(open) = open {
+                            if commas.is_empty() == false {
+                                return Glob::expand_brace(&pattern, open, &commas, i);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+
+        vec![pattern]
+    }
+
+    fn expand_brace(pattern: &[char], open: usize, commas: &[usize], close: usize) -> Vec<Vec<char>> {
+        let mut bounds = vec![open];
+        bounds.extend_from_slice(commas);
+        bounds.push(close);
+
+        let mut expanded = Vec::new();
+        for pair in bounds.windows(2) {
+            let mut alternative = pattern[..open].to_vec();
+            alternative.extend_from_slice(&pattern[pair[0] + 1..pair[1]]);
+            alternative.extend_from_slice(&pattern[close + 1..]);
+            expanded.extend(Glob::expand_braces(alternative));
+        }
+
+        expanded
+    }
+
+    fn match_here(pattern: &[char], path: &[char]) -> bool {
+        let (first, rest) = match pattern.split_first() {
+            Some(split) => split,
+            None => return path.is_empty(),
+        };
+
+        match first {
+            '*' if rest.first() == Some(&'*') => {
+                let rest = &rest[1..];
+                // `**/` can match no folders at all, so `**/a` matches `a`.
+                if rest.first() == Some(&'/') && Glob::match_here(&rest[1..], path) {
+                    return true;
+                }
+                (0..=path.len()).any(|i| Glob::match_here(rest, &path[i..]))
+            }
+            '*' => {
+                let end = path.iter().position(|c| *c == '/').unwrap_or(path.len());
+                (0..=end).any(|i| Glob::match_here(rest, &path[i..]))
+            }
+            '?' => match path.split_first() {
+                Some((c, path)) => *c != '/' && Glob::match_here(rest, path),
+                None => false,
+            },
+            '[' => match (Glob::class_end(rest), path.split_first()) {
+                (Some(end), Some((c, path))) => {
+                    *c != '/' && Glob::class_matches(&rest[..end], *c) && Glob::match_here(&rest[end + 1..], path)
+                }
+                (Some(_), None) => false,
+                (None, _) => Glob::match_literal('[', rest, path),
+            },
+            '\\' if rest.is_empty() == false => Glob::match_literal(rest[0], &rest[1..], path),
+            c => Glob::match_literal(*c, rest, path),
+        }
+    }
+
+    fn match_literal(literal: char, rest: &[char], path: &[char]) -> bool {
+        match path.split_first() {
+            Some((c, path)) => *c == literal && Glob::match_here(rest, path),
+            None => false,
+        }
+    }
+
+    // Where the `]` closing a class is. A `]` straight after the `[` (or the `!`) is part of the class.
+    fn class_end(class: &[char]) -> Option<usize> {
+        let start = match class.first() {
+            Some('!') | Some('^') => 2,
+            _ => 1,
+        };
+
+        class
+            .iter()
+            .skip(start)
+            .position(|c| *c == ']')
+            .map(|position| position + start)
+    }
+
+    fn class_matches(class: &[char], c: char) -> bool {
+        let (negated, class) = match class.split_first() {
+            Some(('!', rest)) | Some(('^', rest)) => (true, rest),
+            _ => (false, class),
+        };
+
+        let mut found = false;
+        let mut i = 0;
+        while i < class.len() {
+            if i + 2 < class.len() && class[i + 1] == '-' {
+                found |= class[i] <= c && c <= class[i + 2];
+                i += 3;
+            } else {
+                found |= class[i] == c;
+                i += 1;
+            }
+        }
+
+        found != negated
+    }
+}
+
+#[cfg(test)]
+mod glob_test {
+    use super::Glob;
+
+    #[test]
+    fn stars() {
+        assert!(Glob::new("*.gml").is_match("a.gml"));
+        assert!(Glob::new("*.gml").is_match("a/b.gml") == false);
+        assert!(Glob::new("**.gml").is_match("a/b.gml"));
+        assert!(Glob::new("**/b.gml").is_match("b.gml"));
+        assert!(Glob::new("a/**/b.gml").is_match("a/b.gml"));
+        assert!(Glob::new("a/**/b.gml").is_match("a/x/y/b.gml"));
+        assert!(Glob::new("extensions/**").is_match("extensions/x/y.gml"));
+        assert!(Glob::new("extensions/**").is_match("scripts/y.gml") == false);
+    }
+
+    #[test]
+    fn classes_and_braces() {
+        assert!(Glob::new("a?.gml").is_match("ab.gml"));
+        assert!(Glob::new("a?.gml").is_match("a/.gml") == false);
+        assert!(Glob::new("[a-c]x").is_match("bx"));
+        assert!(Glob::new("[!a-c]x").is_match("bx") == false);
+        assert!(Glob::new("[ab").is_match("[ab"));
+        assert!(Glob::new("*.{gml,yy}").is_match("a.yy"));
+        assert!(Glob::new("*.{gml,y{y,yp}}").is_match("a.yyp"));
+        assert!(Glob::new("{gml}").is_match("{gml}"));
+        assert!(Glob::new("\\*.gml").is_match("a.gml") == false);
+    }
+}
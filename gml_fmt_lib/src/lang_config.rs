@@ -1,9 +1,16 @@
+use super::editorconfig::EditorConfig;
+use super::glob::Glob;
+use super::parser::DEFAULT_MAX_DEPTH;
+use super::project::Project;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::{ffi::OsStr, fs};
-use toml;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{error::Error, fmt, fs, io};
+use toml::{self, value::Table, Value};
 
-#[derive(Debug, Deserialize, Serialize)]
+const CONFIG_NAMES: [&str; 3] = ["gml_fmt.toml", ".gml_fmt.toml", ".gml_fmt"];
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct LangConfig {
     #[serde(default = "use_spaces")]
     pub use_spaces: bool,
@@ -11,6 +18,70 @@ pub struct LangConfig {
     pub space_size: usize,
     #[serde(default = "newlines_at_end")]
     pub newlines_at_end: usize,
+    #[serde(default = "modernize_legacy_operators")]
+    pub modernize_legacy_operators: bool,
+    #[serde(default = "insert_braces")]
+    pub insert_braces: bool,
+    #[serde(default = "semicolons")]
+    pub semicolons: Semicolons,
+    #[serde(default = "trailing_commas")]
+    pub trailing_commas: TrailingCommas,
+    #[serde(default = "end_of_line")]
+    pub end_of_line: EndOfLine,
+    #[serde(default = "max_depth")]
+    pub max_depth: usize,
+}
+
+/// When the printer ends a statement with a semicolon.
+#[derive(Debug, Deserialize, Serialize, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Semicolons {
+    /// Every statement gets one, whether or not it had one.
+    Always,
+    /// Statements lose theirs, unless that would join them onto the next statement.
+    Never,
+    /// Statements keep what they came in with.
+    Preserve,
+}
+
+/// When the last item of a list gets a comma after it. A list is multiline when its
+/// closing bracket sits on a line of its own, or, for a `var` list, when it runs over
+/// more than one line. `var` lists and function parameters never gain a comma, as that
+/// would change the code, but lose theirs as any other list would.
+#[derive(Debug, Deserialize, Serialize, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingCommas {
+    /// Multiline lists gain one, and single line lists keep what they came in with.
+    Always,
+    /// Lists lose theirs.
+    Never,
+    /// Multiline lists gain one, and single line lists lose theirs.
+    Multiline,
+    /// Lists keep what they came in with.
+    Preserve,
+}
+
+/// The line ending the printer writes.
+#[derive(Debug, Deserialize, Serialize, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EndOfLine {
+    Lf,
+    Crlf,
+    /// Whichever the file ends its first line with.
+    Auto,
+}
+
+impl EndOfLine {
+    /// Settles `Auto` for `source`.
+    pub fn for_source(self, source: &str) -> EndOfLine {
+        match self {
+            EndOfLine::Auto => match source.find('\n') {
+                Some(newline) if source[..newline].ends_with('\r') => EndOfLine::Crlf,
+                _ => EndOfLine::Lf,
+            },
+            end_of_line => end_of_line,
+        }
+    }
 }
 
 fn use_spaces() -> bool {
@@ -25,42 +96,751 @@ fn newlines_at_end() -> usize {
     1
 }
 
+fn modernize_legacy_operators() -> bool {
+    false
+}
+
+fn insert_braces() -> bool {
+    false
+}
+
+fn semicolons() -> Semicolons {
+    Semicolons::Always
+}
+
+fn trailing_commas() -> TrailingCommas {
+    TrailingCommas::Preserve
+}
+
+fn end_of_line() -> EndOfLine {
+    EndOfLine::Auto
+}
+
+fn max_depth() -> usize {
+    DEFAULT_MAX_DEPTH
+}
+
 impl Default for LangConfig {
     fn default() -> Self {
         LangConfig {
             use_spaces: true,
             space_size: 4,
             newlines_at_end: 1,
+            modernize_legacy_operators: false,
+            insert_braces: false,
+            semicolons: Semicolons::Always,
+            trailing_commas: TrailingCommas::Preserve,
+            end_of_line: EndOfLine::Auto,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+/// Builds a `LangConfig` one option at a time, starting from the defaults.
+///
+/// ```
+/// use gml_fmt_lib::{LangConfig, Semicolons};
+///
+/// let lang_config = LangConfig::builder()
+///     .space_size(2)
+///     .semicolons(Semicolons::Preserve)
+///     .build()
+///     .unwrap();
+/// assert_eq!(lang_config.space_size, 2);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LangConfigBuilder {
+    lang_config: LangConfig,
+}
+
+impl LangConfigBuilder {
+    pub fn use_spaces(mut self, use_spaces: bool) -> Self {
+        self.lang_config.use_spaces = use_spaces;
+        self
+    }
+
+    pub fn space_size(mut self, space_size: usize) -> Self {
+        self.lang_config.space_size = space_size;
+        self
+    }
+
+    pub fn newlines_at_end(mut self, newlines_at_end: usize) -> Self {
+        self.lang_config.newlines_at_end = newlines_at_end;
+        self
+    }
+
+    pub fn modernize_legacy_operators(mut self, modernize_legacy_operators: bool) -> Self {
+        self.lang_config.modernize_legacy_operators = modernize_legacy_operators;
+        self
+    }
+
+    pub fn insert_braces(mut self, insert_braces: bool) -> Self {
+        self.lang_config.insert_braces = insert_braces;
+        self
+    }
+
+    pub fn semicolons(mut self, semicolons: Semicolons) -> Self {
+        self.lang_config.semicolons = semicolons;
+        self
+    }
+
+    pub fn trailing_commas(mut self, trailing_commas: TrailingCommas) -> Self {
+        self.lang_config.trailing_commas = trailing_commas;
+        self
+    }
+
+    pub fn end_of_line(mut self, end_of_line: EndOfLine) -> Self {
+        self.lang_config.end_of_line = end_of_line;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.lang_config.max_depth = max_depth;
+        self
+    }
+
+    /// Fails on the same out of range numbers a config file would.
+    pub fn build(self) -> Result<LangConfig, ConfigError> {
+        for key in &["space_size", "newlines_at_end", "max_depth"] {
+            if let Err(kind) = self.lang_config.check_range(key) {
+                let mut error = ConfigError::new(None, kind);
+                error.key = Some(key.to_string());
+                return Err(error);
+            }
         }
+
+        Ok(self.lang_config)
     }
 }
 
 impl LangConfig {
-    pub fn new(input_path: &PathBuf) -> LangConfig {
-        let names = vec![
-            OsStr::new("gml_fmt.toml"),
-            OsStr::new(".gml_fmt.toml"),
-            OsStr::new(".gml_fmt"),
-        ];
-
-        for entry in fs::read_dir(input_path).expect(&format!("Error reading directory {:?}", input_path)) {
-            let entry = entry.expect(&format!("Error reading file"));
-            let path = entry.path();
-
-            if path.is_file() {
-                let fname = path.file_name().expect("Error reading filename.");
-
-                if names.contains(&fname) {
-                    let lang_config: LangConfig = toml::from_str(&fs::read_to_string(path).unwrap()).unwrap();
-                    return lang_config;
+    pub fn builder() -> LangConfigBuilder {
+        LangConfigBuilder::default()
+    }
+
+    pub fn new(input_path: &Path) -> Result<LangConfig, ConfigError> {
+        match LangConfig::config_file_in(input_path) {
+            Some(config_path) => {
+                let layer = Layer::read(&config_path)?;
+                LangConfig::from_table(layer.table, Some(&config_path))
+            }
+            None => Ok(LangConfig::default()),
+        }
+    }
+
+    /// Finds the configuration for a file by walking up from its folder. Options in nearer
+    /// config files win over those further up. The walk stops at a config file which says
+    /// `root = true`, or at the folder holding the project's `.yyp`. If there are no config
+    /// files on the way, we use `fallback`.
+    pub fn for_file(file_path: &Path, fallback: &LangConfig) -> Result<LangConfig, ConfigError> {
+        let layers = LangConfig::discover(file_path)?;
+        if layers.is_empty() {
+            return Ok(fallback.clone());
+        }
+
+        Ok(LangConfig::merge(layers)?.lang_config)
+    }
+
+    // Every config file from `path` up, nearest first, with the overrides for `path` applied.
+    fn discover(path: &Path) -> Result<Vec<Layer>, ConfigError> {
+        let path = LangConfig::canonicalize(path)?;
+        let skip = if path.is_dir() { 0 } else { 1 };
+        let mut layers = Vec::new();
+
+        for directory in path.ancestors().skip(skip) {
+            if let Some(config_path) = LangConfig::config_file_in(directory) {
+                let layer = Layer::read(&config_path)?.for_path(&path);
+                let is_root = layer.is_root;
+                layers.push(layer);
+
+                if is_root {
+                    break;
                 }
             }
+
+            if Project::is_project_folder(directory) {
+                break;
+            }
         }
 
-        LangConfig {
-            newlines_at_end: 1,
-            use_spaces: true,
-            space_size: 4,
+        Ok(layers)
+    }
+
+    // Layers come nearest first, so the first one to set an option wins.
+    fn merge(layers: Vec<Layer>) -> Result<ResolvedConfig, ConfigError> {
+        let nearest_path = layers.iter().find_map(|layer| match &layer.source {
+            ConfigSource::File(path) => Some(path.clone()),
+            _ => None,
+        });
+
+        let mut merged = Table::new();
+        let mut sources = BTreeMap::new();
+        for layer in layers.into_iter().rev() {
+            for (key, value) in layer.table {
+                sources.insert(key.clone(), layer.source.clone());
+                merged.insert(key, value);
+            }
+        }
+
+        let ignore = merged.remove("ignore").and_then(|ignore| ignore.as_bool()) == Some(true);
+        let lang_config = LangConfig::from_table(merged, nearest_path.as_deref())?;
+        Ok(ResolvedConfig {
+            lang_config,
+            sources,
+            ignore,
+        })
+    }
+
+    fn canonicalize(path: &Path) -> Result<PathBuf, ConfigError> {
+        fs::canonicalize(path).map_err(|e| ConfigError::new(Some(path), ConfigErrorKind::Io(e)))
+    }
+
+    // The options `.editorconfig` files set for `path`, one layer for each. Values we
+    // can't use are passed over, as EditorConfig asks, as are properties we have no option
+    // for, like `max_line_length`.
+    fn editorconfig_layers(path: &Path) -> Result<Vec<Layer>, ConfigError> {
+        let editorconfig = EditorConfig::for_file(path)?;
+
+        let mut layers = Vec::new();
+        let mut add_option = |key: &str, value: Value, source: &Path| {
+            if LangConfig::check_option(key, &value).is_ok() {
+                let mut table = Table::new();
+                table.insert(key.to_owned(), value);
+                layers.push(Layer::new(table, ConfigSource::File(source.to_owned())));
+            }
+        };
+
+        match editorconfig.get("indent_style") {
+            Some(("space", source)) => add_option("use_spaces", Value::Boolean(true), source),
+            Some(("tab", source)) => add_option("use_spaces", Value::Boolean(false), source),
+            _ => {}
+        }
+
+        let indent_size = match editorconfig.get("indent_size") {
+            Some(("tab", _)) | None => editorconfig.get("tab_width"),
+            indent_size => indent_size,
+        };
+        if let Some((indent_size, source)) = indent_size {
+            if let Ok(indent_size) = indent_size.parse() {
+                add_option("space_size", Value::Integer(indent_size), source);
+            }
+        }
+
+        match editorconfig.get("insert_final_newline") {
+            Some(("true", source)) => add_option("newlines_at_end", Value::Integer(1), source),
+            Some(("false", source)) => add_option("newlines_at_end", Value::Integer(0), source),
+            _ => {}
+        }
+
+        if let Some((end_of_line, source)) = editorconfig.get("end_of_line") {
+            add_option("end_of_line", Value::String(end_of_line.to_owned()), source);
+        }
+
+        Ok(layers)
+    }
+
+    fn config_file_in(directory: &Path) -> Option<PathBuf> {
+        CONFIG_NAMES
+            .iter()
+            .map(|name| directory.join(name))
+            .find(|path| path.is_file())
+    }
+
+    fn from_table(table: Table, config_path: Option<&Path>) -> Result<LangConfig, ConfigError> {
+        Value::Table(table)
+            .try_into()
+            .map_err(|e| ConfigError::new(config_path, ConfigErrorKind::InvalidValue(e.to_string())))
+    }
+
+    fn check_option(key: &str, value: &Value) -> Result<(), ConfigErrorKind> {
+        if key == "root" {
+            return match value {
+                Value::Boolean(_) => Ok(()),
+                _ => Err(ConfigErrorKind::InvalidValue(format!(
+                    "expected a boolean, found {}",
+                    value.type_str()
+                ))),
+            };
+        }
+
+        let options = LangConfig::option_names();
+        if options.iter().any(|option| option == key) == false {
+            return Err(ConfigErrorKind::UnknownKey {
+                suggestion: LangConfig::closest_option(key, &options),
+            });
+        }
+
+        let mut table = Table::new();
+        table.insert(key.to_owned(), value.clone());
+
+        let lang_config = LangConfig::from_table(table, None).map_err(|e| match e.kind {
+            ConfigErrorKind::InvalidValue(message) => {
+                let suffix = format!(" for key `{}`", key);
+                ConfigErrorKind::InvalidValue(message.trim_end_matches(&suffix).to_owned())
+            }
+            kind => kind,
+        })?;
+
+        lang_config.check_range(key)
+    }
+
+    fn check_range(&self, key: &str) -> Result<(), ConfigErrorKind> {
+        let (value, min, max) = match key {
+            "space_size" => (self.space_size, 1, 16),
+            "newlines_at_end" => (self.newlines_at_end, 0, 10),
+            "max_depth" => (self.max_depth, 1, 100_000),
+            _ => return Ok(()),
+        };
+
+        if value < min || value > max {
+            return Err(ConfigErrorKind::OutOfRange { value, min, max });
+        }
+
+        Ok(())
+    }
+
+    // Every option we know about, taken from the defaults so it never falls out of date.
+    fn option_names() -> Vec<String> {
+        match Value::try_from(LangConfig::default()) {
+            Ok(Value::Table(table)) => table.into_iter().map(|(key, _)| key).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn closest_option(key: &str, options: &[String]) -> Option<String> {
+        options
+            .iter()
+            .map(|option| (edit_distance(key, option), option))
+            .filter(|(distance, _)| *distance <= 3)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, option)| option.clone())
+    }
+}
+
+/// Where an option's value came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigSource {
+    Default,
+    File(PathBuf),
+    CommandLine,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File(path) => write!(f, "{}", path.display()),
+            ConfigSource::CommandLine => write!(f, "--config-override"),
+        }
+    }
+}
+
+// The options set by one config file, or by the command line.
+#[derive(Clone)]
+struct Layer {
+    table: Table,
+    source: ConfigSource,
+    is_root: bool,
+    overrides: Vec<Override>,
+    // When not empty, only files matching these are formatted.
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+    // Where the globs are matched from.
+    directory: PathBuf,
+}
+
+// An `[[overrides]]` section, whose options apply to the files its globs match.
+#[derive(Clone)]
+struct Override {
+    files: Vec<Glob>,
+    table: Table,
+}
+
+impl Layer {
+    fn new(table: Table, source: ConfigSource) -> Layer {
+        Layer {
+            table,
+            source,
+            is_root: false,
+            overrides: Vec::new(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            directory: PathBuf::new(),
+        }
+    }
+
+    /// Reads a config file, checking every key and value in it, so that a mistake is
+    /// reported against the file it was made in.
+    fn read(config_path: &Path) -> Result<Layer, ConfigError> {
+        let contents =
+            fs::read_to_string(config_path).map_err(|e| ConfigError::new(Some(config_path), ConfigErrorKind::Io(e)))?;
+
+        let mut table: Table = toml::from_str(&contents).map_err(|e| {
+            // toml puts the position at the end of its message, but we print our own.
+            let message = e.to_string();
+            let message = message.split(" at line ").next().unwrap_or_default().to_owned();
+
+            let mut error = ConfigError::new(Some(config_path), ConfigErrorKind::Syntax(message));
+            error.line = e.line_col().map(|(line, _)| line + 1);
+            error
+        })?;
+
+        let overrides = match table.remove("overrides") {
+            Some(overrides) => Override::read_all(overrides, config_path, &contents)?,
+            None => Vec::new(),
+        };
+
+        let mut globs_for = |key: &str| match table.remove(key) {
+            Some(value) => Layer::globs(value).map_err(|kind| {
+                let mut error = ConfigError::new(Some(config_path), kind);
+                error.line = Layer::line_of_key(&contents, key, 0);
+                error.key = Some(key.to_owned());
+                error
+            }),
+            None => Ok(Vec::new()),
+        };
+        let include = globs_for("include")?;
+        let exclude = globs_for("exclude")?;
+
+        for (key, value) in &table {
+            if let Err(kind) = LangConfig::check_option(key, value) {
+                let mut error = ConfigError::new(Some(config_path), kind);
+                error.line = Layer::line_of_key(&contents, key, 0);
+                error.key = Some(key.clone());
+                return Err(error);
+            }
+        }
+
+        let is_root = table.remove("root").and_then(|root| root.as_bool()) == Some(true);
+        let directory = config_path.parent().unwrap_or_else(|| Path::new(""));
+        Ok(Layer {
+            table,
+            source: ConfigSource::File(config_path.to_owned()),
+            is_root,
+            overrides,
+            include,
+            exclude,
+            directory: fs::canonicalize(directory).unwrap_or_else(|_| directory.to_owned()),
+        })
+    }
+
+    /// This layer as it applies to `path`, which should be canonical. Later overrides win
+    /// over earlier ones, and all of them win over the options at the top of the file.
+    /// `include` and `exclude` decide whether the file is ignored, unless an override says otherwise.
+    fn for_path(&self, path: &Path) -> Layer {
+        let mut layer = self.clone();
+        let matches = |globs: &[Glob]| globs.iter().any(|glob| glob.covers(&self.directory, path));
+
+        if self.include.is_empty() == false {
+            layer
+                .table
+                .insert("ignore".to_owned(), Value::Boolean(matches(&self.include) == false));
+        }
+        if matches(&self.exclude) {
+            layer.table.insert("ignore".to_owned(), Value::Boolean(true));
+        }
+
+        for this_override in &self.overrides {
+            if matches(&this_override.files) {
+                layer.table.extend(this_override.table.clone());
+            }
+        }
+
+        layer.overrides.clear();
+        layer
+    }
+
+    fn globs(value: Value) -> Result<Vec<Glob>, ConfigErrorKind> {
+        match value {
+            Value::Array(globs) if globs.iter().all(Value::is_str) => {
+                Ok(globs.iter().filter_map(Value::as_str).map(Glob::in_folder).collect())
+            }
+            _ => Err(ConfigErrorKind::InvalidValue(
+                "expected an array of glob strings".to_owned(),
+            )),
+        }
+    }
+
+    // The line `key` is set on in the `section`th `[[overrides]]`, or at the top of the file for 0.
+    fn line_of_key(contents: &str, key: &str, section: usize) -> Option<usize> {
+        let mut current_section = 0;
+        contents
+            .lines()
+            .position(|line| {
+                let line = line.trim_start();
+                if line.starts_with('[') {
+                    current_section += 1;
+                    return false;
+                }
+
+                current_section == section && line.starts_with(key) && line[key.len()..].trim_start().starts_with('=')
+            })
+            .map(|line| line + 1)
+    }
+}
+
+impl Override {
+    fn read_all(overrides: Value, config_path: &Path, contents: &str) -> Result<Vec<Override>, ConfigError> {
+        let error = |kind, key: Option<&str>, line| {
+            let mut error = ConfigError::new(Some(config_path), kind);
+            error.key = key.map(str::to_owned);
+            error.line = line;
+            error
+        };
+
+        let overrides = match overrides {
+            Value::Array(overrides) => overrides,
+            other => {
+                let message = format!("expected an array of tables, found {}", other.type_str());
+                let line = Layer::line_of_key(contents, "overrides", 0);
+                return Err(error(ConfigErrorKind::InvalidValue(message), Some("overrides"), line));
+            }
+        };
+
+        let mut read = Vec::new();
+        for (i, this_override) in overrides.into_iter().enumerate() {
+            let line_of_key = |key| Layer::line_of_key(contents, key, i + 1);
+            let mut table = match this_override {
+                Value::Table(table) => table,
+                other => {
+                    let message = format!("expected an array of tables, found {}", other.type_str());
+                    return Err(error(ConfigErrorKind::InvalidValue(message), Some("overrides"), None));
+                }
+            };
+
+            let files = match table.remove("files") {
+                Some(files) => Layer::globs(files).map_err(|kind| error(kind, Some("files"), line_of_key("files")))?,
+                None => {
+                    let message = "every override needs a `files` list".to_owned();
+                    return Err(error(ConfigErrorKind::InvalidValue(message), None, None));
+                }
+            };
+
+            for (key, value) in &table {
+                let checked = match key.as_str() {
+                    "ignore" if value.is_bool() => Ok(()),
+                    "ignore" => Err(ConfigErrorKind::InvalidValue(format!(
+                        "expected a boolean, found {}",
+                        value.type_str()
+                    ))),
+                    "root" => Err(ConfigErrorKind::UnknownKey { suggestion: None }),
+                    _ => LangConfig::check_option(key, value),
+                };
+
+                if let Err(kind) = checked {
+                    return Err(error(kind, Some(key), line_of_key(key)));
+                }
+            }
+
+            read.push(Override { files, table });
+        }
+
+        Ok(read)
+    }
+}
+
+/// Works out the configuration for each file we format. A config file given on the
+/// command line takes the place of the ones we would find, and overrides from the
+/// command line win over everything. Options from `.editorconfig` files are used
+/// where no config file sets them.
+#[derive(Default)]
+pub struct ConfigResolver {
+    fallback: Option<Layer>,
+    config_file: Option<Layer>,
+    overrides: Table,
+}
+
+/// A file's configuration, along with where each option came from.
+#[derive(Debug)]
+pub struct ResolvedConfig {
+    pub lang_config: LangConfig,
+    pub sources: BTreeMap<String, ConfigSource>,
+    /// Set by an override with `ignore = true`, or by `include` and `exclude`. These
+    /// files aren't formatted at all.
+    pub ignore: bool,
+}
+
+impl ConfigResolver {
+    /// The config file in `directory`, if there is one, is used for files which have none of their own.
+    pub fn new(directory: &Path) -> Result<ConfigResolver, ConfigError> {
+        let fallback = match LangConfig::config_file_in(directory) {
+            Some(config_path) => Some(Layer::read(&config_path)?),
+            None => None,
+        };
+
+        Ok(ConfigResolver {
+            fallback,
+            ..Default::default()
+        })
+    }
+
+    pub fn set_config_file(&mut self, config_path: &Path) -> Result<(), ConfigError> {
+        self.config_file = Some(Layer::read(config_path)?);
+        Ok(())
+    }
+
+    /// Takes an override like `space_size=2`. Values are read as TOML, but a bare word
+    /// is taken as a string, so `semicolons=never` works too.
+    pub fn add_override(&mut self, key_value: &str) -> Result<(), ConfigError> {
+        let mut parts = key_value.splitn(2, '=');
+        let key = parts.next().unwrap_or_default().trim();
+        let value = match parts.next() {
+            Some(value) => value.trim(),
+            None => {
+                let message = format!("expected `key=value`, found `{}`", key_value);
+                return Err(ConfigError::new(None, ConfigErrorKind::Syntax(message)));
+            }
+        };
+
+        let value = match toml::from_str::<Table>(&format!("value = {}", value)) {
+            Ok(mut table) => table.remove("value").unwrap_or_else(|| Value::String(value.to_owned())),
+            Err(_) => Value::String(value.to_owned()),
+        };
+
+        if let Err(kind) = LangConfig::check_option(key, &value) {
+            let mut error = ConfigError::new(None, kind);
+            error.key = Some(key.to_owned());
+            return Err(error);
+        }
+
+        self.overrides.insert(key.to_owned(), value);
+        Ok(())
+    }
+
+    /// Resolves the configuration for a file, or for a folder as if for a file inside it.
+    pub fn resolve(&self, path: &Path) -> Result<ResolvedConfig, ConfigError> {
+        let path = LangConfig::canonicalize(path)?;
+        let mut layers = Vec::new();
+        if self.overrides.is_empty() == false {
+            layers.push(Layer::new(self.overrides.clone(), ConfigSource::CommandLine));
+        }
+
+        match &self.config_file {
+            Some(config_file) => layers.push(config_file.for_path(&path)),
+            None => {
+                let discovered = LangConfig::discover(&path)?;
+                if discovered.is_empty() {
+                    layers.extend(self.fallback.as_ref().map(|fallback| fallback.for_path(&path)));
+                } else {
+                    layers.extend(discovered);
+                }
+            }
+        }
+        layers.extend(LangConfig::editorconfig_layers(&path)?);
+
+        LangConfig::merge(layers)
+    }
+}
+
+impl ResolvedConfig {
+    /// Every option as TOML, with a comment saying where its value came from.
+    pub fn to_toml(&self) -> String {
+        let options = match Value::try_from(&self.lang_config) {
+            Ok(Value::Table(table)) => table,
+            _ => Table::new(),
+        };
+
+        let mut output = String::new();
+        if self.ignore {
+            let source = self.sources.get("ignore").unwrap_or(&ConfigSource::Default);
+            output.push_str(&format!("ignore = true # {}\n", source));
+        }
+
+        for (key, value) in options {
+            let source = self.sources.get(&key).unwrap_or(&ConfigSource::Default);
+
+            let mut line = Table::new();
+            line.insert(key, value);
+            let line = toml::to_string(&line).unwrap_or_default();
+
+            output.push_str(&format!("{} # {}\n", line.trim_end(), source));
+        }
+
+        output
+    }
+}
+
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// A config file we couldn't use. Lines are one-based.
+#[derive(Debug)]
+pub struct ConfigError {
+    /// `None` when the mistake was made on the command line, or with a `LangConfigBuilder`.
+    pub path: Option<PathBuf>,
+    pub line: Option<usize>,
+    pub key: Option<String>,
+    pub kind: ConfigErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ConfigErrorKind {
+    Io(io::Error),
+    Syntax(String),
+    UnknownKey { suggestion: Option<String> },
+    InvalidValue(String),
+    OutOfRange { value: usize, min: usize, max: usize },
+}
+
+impl ConfigError {
+    pub(crate) fn new(path: Option<&Path>, kind: ConfigErrorKind) -> ConfigError {
+        ConfigError {
+            path: path.map(Path::to_owned),
+            line: None,
+            key: None,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}", path.display())?,
+            None => write!(f, "command line")?,
+        }
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        write!(f, ": ")?;
+
+        let key = self.key.as_deref().unwrap_or_default();
+        match &self.kind {
+            ConfigErrorKind::Io(e) => write!(f, "{}", e),
+            ConfigErrorKind::Syntax(message) => write!(f, "{}", message),
+            ConfigErrorKind::UnknownKey {
+                suggestion: Some(suggestion),
+            } => {
+                write!(f, "unknown option `{}`, did you mean `{}`?", key, suggestion)
+            }
+            ConfigErrorKind::UnknownKey { suggestion: None } => write!(f, "unknown option `{}`", key),
+            ConfigErrorKind::InvalidValue(message) if key.is_empty() => write!(f, "{}", message),
+            ConfigErrorKind::InvalidValue(message) => write!(f, "invalid value for `{}`: {}", key, message),
+            ConfigErrorKind::OutOfRange { value, min, max } => {
+                write!(f, "`{}` must be between {} and {}, but it is {}", key, min, max, value)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            ConfigErrorKind::Io(e) => Some(e),
+            _ => None,
         }
     }
 }
@@ -1,4 +1,7 @@
-#[derive(Debug, PartialEq, Copy, Clone)]
+use serde::Serialize;
+
+/// What a token is. Those with text carry the slice of the source they came from.
+#[derive(Debug, PartialEq, Copy, Clone, Serialize)]
 pub enum TokenType<'a> {
     LeftParen,
     RightParen,
@@ -104,11 +107,21 @@ pub enum TokenType<'a> {
     UnidentifiedInput(&'a str),
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+/// A token, with the line and column it starts on, both counted from 0.
+#[derive(Debug, PartialEq, Copy, Clone, Serialize)]
 pub struct Token<'a> {
     pub token_type: TokenType<'a>,
     pub line_number: u32,
     pub column_number: u32,
+    pub span: Span,
+}
+
+/// Where something is in the source, from the byte it starts on to the byte after it ends.
+/// Tokens and nodes we make up, rather than read, have an empty span at 0.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
 }
 
 impl<'a> Token<'a> {
@@ -117,6 +130,14 @@ impl<'a> Token<'a> {
             token_type,
             line_number,
             column_number,
+            span: Span::default(),
+        }
+    }
+
+    pub fn with_span(self, start: usize, end: usize) -> Token<'a> {
+        Token {
+            span: Span { start, end },
+            ..self
         }
     }
 }
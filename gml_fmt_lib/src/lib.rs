@@ -1,30 +1,113 @@
 #![allow(clippy::bool_comparison)]
 
+mod backup;
+mod brace_inserter;
 mod config;
+mod cst;
+mod dump;
+mod editorconfig;
+mod encoding;
+mod error;
 mod expressions;
+mod glob;
 mod lang_config;
 mod lex_token;
+mod line_index;
+mod modernizer;
 mod parser;
 mod printer;
+mod project;
 mod scanner;
+mod source_map;
 mod statements;
+mod verify;
+mod visitor;
 
 use anyhow::Result as AnyResult;
-use parser::Parser;
+use backup::{write_atomically, Backup};
+use brace_inserter::BraceInserter;
+use encoding::Encoding;
+use modernizer::Modernizer;
 use printer::Printer;
-use std::fs;
+use std::path::Path;
+use std::{fs, io};
 
+const BYTE_ORDER_MARK: char = '\u{feff}';
+const IGNORE_COMMENT: &str = "// @gml_fmt ignore";
+
+pub use backup::{restore_latest, Restored};
 pub use config::{Config, PrintFlags};
-pub use lang_config::LangConfig;
+pub use cst::{build_cst, SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken};
+pub use dump::{dump_ast, dump_tokens, DumpFormat};
+pub use error::FormatError;
+pub use expressions::{CommentsAndNewlines, DSAccess, Expr, ExprBox, ExprBoxInterior};
+pub use lang_config::{
+    ConfigError, ConfigErrorKind, ConfigResolver, ConfigSource, EndOfLine, LangConfig, LangConfigBuilder,
+    ResolvedConfig, Semicolons, TrailingCommas,
+};
+pub use lex_token::{Span, Token, TokenType};
+pub use line_index::{LineCol, LineIndex};
+pub use modernizer::{Rewrite, RewriteKind};
+pub use parser::{ParseError, Parser, DEFAULT_MAX_DEPTH};
+pub use project::{Project, ProjectError, ProjectErrorKind, Resource, ResourceKind};
+pub use source_map::{Mapping, SourceMap};
+pub use statements::{
+    Case, CaseType, DelimitedLine, DelimitedLines, MacroBody, MacroDefinition, Statement, StatementWrapper, StmtBox,
+    VariableDecl,
+};
+pub use verify::{CheckError, VerifyError};
+pub use visitor::{
+    walk_ast, walk_ast_mut, walk_expr, walk_expr_mut, walk_statement, walk_statement_mut, Visitor, VisitorMut,
+};
 
-pub fn run_with_config(config: &Config, lang_config: &LangConfig) -> AnyResult<()> {
+/// Formats every file in `config`, each with the configuration `config_resolver` finds for it.
+/// Files which can't be formatted are reported on stderr and left alone, and make this give
+/// an error once every other file is done.
+pub fn run_with_config(config: &Config, config_resolver: &ConfigResolver) -> AnyResult<()> {
     let log = config.print_flags.contains(PrintFlags::LOGS);
     let overwrite = config.print_flags.contains(PrintFlags::OVERWRITE);
+    let check = config.print_flags.contains(PrintFlags::VERIFY);
+    let check_idempotence = config.print_flags.contains(PrintFlags::CHECK_IDEMPOTENCE);
+    let mut backup = if config.print_flags.contains(PrintFlags::BACKUP) {
+        Some(Backup::new(&config.root))
+    } else {
+        None
+    };
 
+    let mut failures = 0;
     for this_file in &config.files {
-        let contents = fs::read_to_string(this_file)?;
+        let lang_config = match config_resolver.resolve(this_file) {
+            Ok(resolved) if resolved.ignore => continue,
+            Ok(resolved) => resolved.lang_config,
+            Err(e) => {
+                eprintln!("Could not configure file {:?}", this_file);
+                eprintln!("{}", e);
+                failures += 1;
+                continue;
+            }
+        };
+        let bytes = match fs::read(this_file) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Could not read file {:?}", this_file);
+                eprintln!("{}", e);
+                failures += 1;
+                continue;
+            }
+        };
+        // Old projects are often not in UTF-8, so we read them in whatever they are in, and write them back the same way.
+        let (contents, encoding) = match Encoding::decode(&bytes) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                eprintln!("Skipping file {:?}, since {}", this_file, e);
+                continue;
+            }
+        };
+        if encoding != Encoding::Utf8 && log {
+            println!("Reading file {:?} as {:?}.", this_file, encoding);
+        }
 
-        if contents.contains("// @gml_fmt ignore") {
+        if contents.contains(IGNORE_COMMENT) {
             continue;
         }
 
@@ -39,8 +122,14 @@ pub fn run_with_config(config: &Config, lang_config: &LangConfig) -> AnyResult<(
             None
         };
 
-        match run(&contents, lang_config, ast_log.as_mut()) {
+        let mut rewrites = Vec::new();
+
+        match format_source(&contents, &lang_config, ast_log.as_mut(), &mut rewrites) {
             Ok(output) => {
+                for rewrite in &rewrites {
+                    println!("{}:{}", this_file.display(), rewrite);
+                }
+
                 if log {
                     println!("=========OUTPUT=========");
                     println!("{}", output);
@@ -51,44 +140,204 @@ pub fn run_with_config(config: &Config, lang_config: &LangConfig) -> AnyResult<(
                     println!("{}", ast);
                 }
 
+                if check_idempotence {
+                    let checked = format_str(&output, &lang_config)
+                        .map_err(CheckError::Format)
+                        .and_then(|second_pass| verify::check_second_pass(&output, &second_pass));
+
+                    if let Err(e) = checked {
+                        eprintln!(
+                            "Could not format file {:?} the same way twice, so it was left alone",
+                            this_file
+                        );
+                        eprintln!("{}", e);
+                        failures += 1;
+                        continue;
+                    }
+                }
+
+                if check {
+                    let reformatted = format_source(&output, &lang_config, None, &mut Vec::new());
+                    let verified = match &reformatted {
+                        Ok(reformatted) => verify::verify(&contents, &output, reformatted, &lang_config, &rewrites)
+                            .map_err(|e| e.to_string()),
+                        Err(e) => Err(format!("the output could not be parsed: {}", e)),
+                    };
+
+                    if let Err(e) = verified {
+                        eprintln!("Could not verify file {:?}, so it was left alone", this_file);
+                        eprintln!("{}", e);
+                        failures += 1;
+                        continue;
+                    }
+                }
+
                 if overwrite {
-                    fs::write(this_file, output)?;
+                    let output = match encoding.encode(&output) {
+                        Ok(output) => output,
+                        Err(c) => {
+                            eprintln!(
+                                "Could not write file {:?}, since {:?} can't be written in {:?}",
+                                this_file, c, encoding
+                            );
+                            failures += 1;
+                            continue;
+                        }
+                    };
+
+                    // Leaving unchanged files alone keeps their modification times.
+                    if output == bytes {
+                        continue;
+                    }
+
+                    if let Some(backup) = &mut backup {
+                        if let Err(e) = backup.save(this_file, &bytes) {
+                            eprintln!("Could not back up file {:?}, so it was not written", this_file);
+                            eprintln!("{}", e);
+                            failures += 1;
+                            continue;
+                        }
+                    }
+
+                    if let Err(e) = write_atomically(this_file, &output) {
+                        eprintln!("Could not write file {:?}", this_file);
+                        eprintln!("{}", e);
+                        failures += 1;
+                    }
                 }
             }
             Err(e) => {
-                println!("Could not parse file {:?}", this_file);
-                println!("{}", e);
+                eprintln!("Could not parse file {:?}", this_file);
+                eprintln!("{}", FormatError::parse(&contents, e));
+                failures += 1;
             }
         }
     }
 
-    Ok(())
+    match failures {
+        0 => Ok(()),
+        1 => anyhow::bail!("1 file could not be formatted"),
+        _ => anyhow::bail!("{} files could not be formatted", failures),
+    }
+}
+
+/// Formats `source` with `lang_config`.
+pub fn format_str(source: &str, lang_config: &LangConfig) -> Result<String, FormatError> {
+    format_source(source, lang_config, None, &mut Vec::new()).map_err(|e| FormatError::parse(source, e))
 }
 
-pub fn run(source: &str, lang_config: &LangConfig, print_ast: Option<&mut String>) -> AnyResult<String> {
+/// Formats the file at `path` with the configuration `config_resolver` finds for it, and
+/// gives back the output without writing it. Files the configuration ignores, or which
+/// contain `// @gml_fmt ignore`, give `None`. Files not in UTF-8 are read as
+/// `read_source` reads them.
+pub fn format_file(path: &Path, config_resolver: &ConfigResolver) -> Result<Option<String>, FormatError> {
+    let contents = read_source(path)?;
+
+    let resolved = config_resolver.resolve(path)?;
+    if resolved.ignore || contents.contains(IGNORE_COMMENT) {
+        return Ok(None);
+    }
+    format_str(&contents, &resolved.lang_config).map(Some)
+}
+
+/// Reads the file at `path` as `run_with_config` reads it, from UTF-8, UTF-16 or Windows-1252.
+pub fn read_source(path: &Path) -> Result<String, FormatError> {
+    let io_error = |error| FormatError::Io {
+        path: path.to_owned(),
+        error,
+    };
+    let bytes = fs::read(path).map_err(io_error)?;
+    let (contents, _) =
+        Encoding::decode(&bytes).map_err(|e| io_error(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+    Ok(contents)
+}
+
+/// Formats `source`, then formats the output again, and only gives the output back if the
+/// second pass left it as it was.
+pub fn format_checked(source: &str, lang_config: &LangConfig) -> Result<String, CheckError> {
+    let first_pass = format_str(source, lang_config).map_err(CheckError::Format)?;
+    let second_pass = format_str(&first_pass, lang_config).map_err(CheckError::Format)?;
+
+    verify::check_second_pass(&first_pass, &second_pass)?;
+    Ok(first_pass)
+}
+
+/// Formats `source` with `lang_config`, and gives back a map from the output to the source
+/// with it.
+pub fn format_with_source_map(source: &str, lang_config: &LangConfig) -> Result<(String, SourceMap), FormatError> {
+    format_source_mapped(source, lang_config, None, &mut Vec::new()).map_err(|e| FormatError::parse(source, e))
+}
+
+/// Formats `source` with `lang_config`, and gives back where `cursor_offset`, a byte
+/// offset in `source`, ended up in the output, so editors can keep the cursor in place.
+pub fn format_with_cursor(
+    source: &str,
+    cursor_offset: usize,
+    lang_config: &LangConfig,
+) -> Result<(String, usize), FormatError> {
+    let (output, source_map) = format_with_source_map(source, lang_config)?;
+    let cursor_offset = source_map.output_offset(cursor_offset);
+    Ok((output, cursor_offset))
+}
+
+fn format_source(
+    source: &str,
+    lang_config: &LangConfig,
+    print_ast: Option<&mut String>,
+    rewrites: &mut Vec<Rewrite>,
+) -> AnyResult<String> {
+    format_source_mapped(source, lang_config, print_ast, rewrites).map(|(output, _)| output)
+}
+
+// The printer leaves out the byte order mark, so we put it back.
+fn format_source_mapped(
+    source: &str,
+    lang_config: &LangConfig,
+    print_ast: Option<&mut String>,
+    rewrites: &mut Vec<Rewrite>,
+) -> AnyResult<(String, SourceMap)> {
     let source_size = source.len();
-    match Parser::new(source).build_ast() {
-        Ok(ast) => {
-            if let Some(give_ast) = print_ast {
-                *give_ast = format!("{:#?}", ast);
-            }
+    let lang_config = LangConfig {
+        end_of_line: lang_config.end_of_line.for_source(source),
+        ..lang_config.clone()
+    };
 
-            let printer = Printer::new(source_size / 2, lang_config).autoformat(&ast);
+    let mut ast = Parser::new(source).max_depth(lang_config.max_depth).build_ast()?;
 
-            Ok(printer.get_output(source_size))
-        }
+    if lang_config.modernize_legacy_operators {
+        *rewrites = Modernizer::new().modernize(&mut ast);
+    }
+
+    if lang_config.insert_braces {
+        BraceInserter::insert_braces(&mut ast);
+    }
 
-        Err(e) => {
-            anyhow::bail!("{}", e);
+    if let Some(give_ast) = print_ast {
+        *give_ast = format!("{:#?}", ast);
+    }
+
+    let printer = Printer::new(source_size / 2, &lang_config).autoformat(&ast);
+
+    let (mut output, mut mappings) = printer.get_output(source_size);
+    if source.starts_with(BYTE_ORDER_MARK) {
+        output.insert(0, BYTE_ORDER_MARK);
+        for mapping in &mut mappings {
+            mapping.output.start += BYTE_ORDER_MARK.len_utf8();
+            mapping.output.end += BYTE_ORDER_MARK.len_utf8();
         }
     }
+    let source_map = SourceMap::new(mappings, source_size, output.len());
+    Ok((output, source_map))
 }
 
+/// Formats `source`, with the default configuration if none is given. `format_str` gives
+/// a `FormatError` instead.
 pub fn run_snippet(source: &str, lang_config: Option<LangConfig>) -> AnyResult<String> {
-    let source_size = source.len();
-    let ast = Parser::new(source).build_ast()?;
-    let config = lang_config.unwrap_or_default();
-    let printer = Printer::new(source_size / 2, &config).autoformat(&ast);
+    Ok(format_str(source, &lang_config.unwrap_or_default())?)
+}
 
-    Ok(printer.get_output(source_size))
+/// Formats `source`, and writes its AST into `print_ast` if given. `format_str` gives a
+/// `FormatError` instead.
+pub fn run(source: &str, lang_config: &LangConfig, print_ast: Option<&mut String>) -> AnyResult<String> {
+    format_source(source, lang_config, print_ast, &mut Vec::new())
 }
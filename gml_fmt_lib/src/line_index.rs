@@ -0,0 +1,147 @@
+/// A line and column, both counted from 0. Columns are in bytes of UTF-8 or in UTF-16
+/// code units, depending on which `LineIndex` method made them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LineCol {
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Converts between byte offsets in a source, such as the ones in a `Span`, and lines and
+/// columns. Editors speaking the Language Server Protocol count columns in UTF-16 code
+/// units, so there are `_utf16` forms for them. Lines end at `\n`, as they do for tokens.
+pub struct LineIndex<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(source: &'a str) -> LineIndex<'a> {
+        let mut line_starts = vec![0];
+        line_starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+        LineIndex { source, line_starts }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Where `offset` is, with its column in bytes. Offsets past the end are taken as the end.
+    pub fn line_col(&self, offset: usize) -> LineCol {
+        let (line, line_start, offset) = self.find_line(offset);
+        LineCol {
+            line: line as u32,
+            column: (offset - line_start) as u32,
+        }
+    }
+
+    /// Where `offset` is, with its column in UTF-16 code units.
+    pub fn line_col_utf16(&self, offset: usize) -> LineCol {
+        let (line, line_start, offset) = self.find_line(offset);
+        LineCol {
+            line: line as u32,
+            column: self.source[line_start..offset].encode_utf16().count() as u32,
+        }
+    }
+
+    /// The offset of `line_col`, with its column in bytes. Gives `None` for positions past
+    /// the end of their line, or inside a character.
+    pub fn offset(&self, line_col: LineCol) -> Option<usize> {
+        let line = self.line(line_col.line)?;
+        let column = line_col.column as usize;
+        if line.is_char_boundary(column) == false {
+            return None;
+        }
+        Some(self.line_starts[line_col.line as usize] + column)
+    }
+
+    /// The offset of `line_col`, with its column in UTF-16 code units.
+    pub fn offset_utf16(&self, line_col: LineCol) -> Option<usize> {
+        let line = self.line(line_col.line)?;
+        let mut units = 0;
+        for (i, c) in line.char_indices() {
+            if units == line_col.column as usize {
+                return Some(self.line_starts[line_col.line as usize] + i);
+            }
+            if units > line_col.column as usize {
+                return None;
+            }
+            units += c.len_utf16();
+        }
+
+        if units == line_col.column as usize {
+            Some(self.line_starts[line_col.line as usize] + line.len())
+        } else {
+            None
+        }
+    }
+
+    // The line's text, without its `\n`.
+    fn line(&self, line: u32) -> Option<&'a str> {
+        let start = *self.line_starts.get(line as usize)?;
+        let end = match self.line_starts.get(line as usize + 1) {
+            Some(next_start) => next_start - 1,
+            None => self.source.len(),
+        };
+        Some(&self.source[start..end])
+    }
+
+    // Offsets inside a character are moved back to its start.
+    fn find_line(&self, offset: usize) -> (usize, usize, usize) {
+        let mut offset = offset.min(self.source.len());
+        while self.source.is_char_boundary(offset) == false {
+            offset -= 1;
+        }
+
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        };
+        (line, self.line_starts[line], offset)
+    }
+}
+
+#[cfg(test)]
+mod line_index_test {
+    use super::{LineCol, LineIndex};
+
+    fn line_col(line: u32, column: u32) -> LineCol {
+        LineCol { line, column }
+    }
+
+    #[test]
+    fn utf8_columns() {
+        let index = LineIndex::new("var a;\n\nb = \"é\";");
+        assert_eq!(index.line_count(), 3);
+        assert_eq!(index.line_col(0), line_col(0, 0));
+        assert_eq!(index.line_col(6), line_col(0, 6));
+        assert_eq!(index.line_col(7), line_col(1, 0));
+        assert_eq!(index.line_col(8), line_col(2, 0));
+        assert_eq!(index.line_col(13), line_col(2, 5));
+        assert_eq!(index.line_col(14), line_col(2, 5));
+        assert_eq!(index.line_col(15), line_col(2, 7));
+        assert_eq!(index.line_col(100), line_col(2, 9));
+
+        assert_eq!(index.offset(line_col(0, 6)), Some(6));
+        assert_eq!(index.offset(line_col(2, 7)), Some(15));
+        assert_eq!(index.offset(line_col(2, 9)), Some(17));
+        assert_eq!(index.offset(line_col(2, 10)), None);
+        assert_eq!(index.offset(line_col(2, 6)), None);
+        assert_eq!(index.offset(line_col(0, 7)), None);
+        assert_eq!(index.offset(line_col(3, 0)), None);
+    }
+
+    #[test]
+    fn utf16_columns() {
+        // `😀` is four bytes of UTF-8, and two units of UTF-16.
+        let index = LineIndex::new("a\n\"😀é\" + b");
+        assert_eq!(index.line_col_utf16(3), line_col(1, 1));
+        assert_eq!(index.line_col_utf16(7), line_col(1, 3));
+        assert_eq!(index.line_col_utf16(9), line_col(1, 4));
+        assert_eq!(index.line_col_utf16(14), line_col(1, 9));
+
+        assert_eq!(index.offset_utf16(line_col(1, 3)), Some(7));
+        assert_eq!(index.offset_utf16(line_col(1, 2)), None);
+        assert_eq!(index.offset_utf16(line_col(1, 9)), Some(14));
+        assert_eq!(index.offset_utf16(line_col(1, 10)), None);
+    }
+}
@@ -0,0 +1,175 @@
+use super::expressions::*;
+use super::lex_token::{Token, TokenType};
+use super::statements::*;
+use super::visitor::{walk_ast_mut, walk_expr_mut, walk_statement_mut, VisitorMut};
+use std::fmt;
+
+/// Rewrites GML 1.x operators into their modern forms: `<>` becomes `!=`,
+/// `then` is dropped, and a bare `=` in an `if`, `while`, `with`, `repeat` or
+/// `until` condition becomes `==`.
+#[derive(Default)]
+pub struct Modernizer {
+    rewrites: Vec<Rewrite>,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Rewrite {
+    pub kind: RewriteKind,
+    pub line_number: u32,
+    pub column_number: u32,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum RewriteKind {
+    LessThanGreaterThan,
+    Then,
+    EqualInCondition,
+}
+
+impl fmt::Display for Rewrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self.kind {
+            RewriteKind::LessThanGreaterThan => "rewrote `<>` to `!=`",
+            RewriteKind::Then => "removed `then`",
+            RewriteKind::EqualInCondition => "rewrote `=` to `==` in condition",
+        };
+
+        write!(
+            f,
+            "{}:{}: {}",
+            self.line_number + 1,
+            self.column_number + 1,
+            description
+        )
+    }
+}
+
+impl Modernizer {
+    pub fn new() -> Modernizer {
+        Modernizer { rewrites: Vec::new() }
+    }
+
+    pub fn modernize(mut self, ast: &mut [StmtBox<'_>]) -> Vec<Rewrite> {
+        walk_ast_mut(&mut self, ast);
+
+        self.rewrites
+            .sort_by_key(|rewrite| (rewrite.line_number, rewrite.column_number));
+        self.rewrites
+    }
+
+    /// Turns every bare `=` in a condition into `==`. We follow the condition through
+    /// groupings and operators, but not into calls or accessors, where an `=` is
+    /// somebody else's business.
+    /// A chain like `a == 1 || a == 2` is followed down its left side in a loop, as its tree
+    /// is as deep as the chain is long.
+    fn condition(&mut self, expr: &mut ExprBoxInterior<'_>) {
+        let mut expr = expr;
+        loop {
+            if matches!(&expr.expr, Expr::Assign { operator, .. } if operator.token_type == TokenType::Equal) {
+                let placeholder = Expr::Newline;
+                if let Expr::Assign {
+                    left,
+                    mut operator,
+                    comments_and_newlines_between_op_and_r,
+                    right,
+                } = std::mem::replace(&mut expr.expr, placeholder)
+                {
+                    self.record(RewriteKind::EqualInCondition, &operator);
+
+                    operator.token_type = TokenType::EqualEqual;
+                    expr.expr = Expr::Binary {
+                        left,
+                        operator,
+                        comments_and_newlines_between_op_and_r,
+                        right,
+                    };
+                }
+            }
+
+            match &mut expr.expr {
+                Expr::Binary { left, right, .. } => {
+                    self.condition(right);
+                    expr = left;
+                }
+                Expr::Grouping { expressions, .. } => {
+                    for expression in expressions {
+                        self.condition(expression);
+                    }
+                    return;
+                }
+                Expr::Unary { right, .. } => expr = right,
+                _ => return,
+            }
+        }
+    }
+
+    fn record(&mut self, kind: RewriteKind, token: &Token<'_>) {
+        self.rewrites.push(Rewrite::new(kind, token));
+    }
+}
+
+impl<'a> VisitorMut<'a> for Modernizer {
+    fn visit_statement_mut(&mut self, stmt: &mut StatementWrapper<'a>) {
+        match &mut stmt.statement {
+            Statement::If { condition, .. }
+            | Statement::WhileWithRepeat { condition, .. }
+            | Statement::DoUntil { condition, .. } => self.condition(condition),
+            _ => {}
+        }
+
+        walk_statement_mut(self, stmt);
+    }
+
+    // A chain like `a <> b <> c` is walked down its left side in a loop, as its tree is as
+    // deep as the chain is long.
+    fn visit_expr_mut(&mut self, expr: &mut ExprBoxInterior<'a>) {
+        let mut expr = expr;
+        while let Expr::Binary { .. } = expr.expr {
+            match &mut expr.expr {
+                Expr::Binary {
+                    left,
+                    operator,
+                    comments_and_newlines_between_op_and_r,
+                    right,
+                } => {
+                    if operator.token_type == TokenType::LessThanGreaterThan {
+                        self.record(RewriteKind::LessThanGreaterThan, operator);
+                        operator.token_type = TokenType::BangEqual;
+                    }
+
+                    self.visit_comments_mut(comments_and_newlines_between_op_and_r);
+                    self.visit_expr_mut(right);
+                    self.visit_comments_mut(&mut expr.trailing_comments);
+                    expr = left;
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_comments_mut(&mut self, comments: &mut CommentsAndNewlines<'a>) {
+        if let Some(vec) = comments {
+            let rewrites = &mut self.rewrites;
+            vec.retain(|token| {
+                if token.token_type == TokenType::Then {
+                    rewrites.push(Rewrite::new(RewriteKind::Then, token));
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+    }
+}
+
+impl Rewrite {
+    fn new(kind: RewriteKind, token: &Token<'_>) -> Rewrite {
+        Rewrite {
+            kind,
+            line_number: token.line_number,
+            column_number: token.column_number,
+        }
+    }
+}
@@ -1,32 +1,136 @@
 use super::expressions::*;
 use super::lex_token::TokenType;
 use super::lex_token::*;
+use super::printer::Printer;
 use super::scanner::Scanner;
 use super::statements::*;
 use anyhow::Result as AnyResult;
-use std::iter::Peekable;
+use std::{error::Error, fmt};
 
+/// Builds the AST for a GML source. The tree borrows its text from the source, and keeps
+/// every comment and newline, but not the whitespace between tokens. `build_cst` keeps
+/// that too.
 pub struct Parser<'a> {
-    pub ast: Vec<StmtBox<'a>>,
+    ast: Vec<StmtBox<'a>>,
     allow_unidentified: bool,
-    scanner: Peekable<Scanner<'a>>,
+    scanner: Tokens<'a>,
     can_pair: bool,
     leftover_stmts: Vec<StmtBox<'a>>,
     check_leftovers: bool,
+    depth: usize,
+    // The deepest `depth` reached in the access chain being parsed, or anywhere if there
+    // isn't one.
+    deepest: usize,
+    max_depth: usize,
+}
+
+/// Why `Parser::build_ast` stopped, which is what its `anyhow::Error` holds. `span` is the
+/// token it stopped at, or an empty span where the tokens ran out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+/// How deep `Parser` lets code nest unless told otherwise. Every statement and operator
+/// inside another is a level, and every bracket is three. A chain of operators like
+/// `a + b + c` isn't nested, and is no level at all. A chain of accesses and calls like
+/// `a[b].c(d)` is a level for every three links in it.
+pub const DEFAULT_MAX_DEPTH: usize = 350;
+
+// A level is about the same amount of stack whatever it is made of. Brackets take about
+// three times the stack of a statement, and a link of an access chain about a third.
+const EXPRESSION_LEVELS: usize = 3;
+const LINKS_PER_LEVEL: usize = 3;
+
+// The scanner, made peekable, which remembers where the tokens it has given out end, so
+// every node can have a span. Comments and newlines after a node aren't part of it.
+struct Tokens<'a> {
+    scanner: Scanner<'a>,
+    peeked: Option<Option<Token<'a>>>,
+    previous_end: usize,
+    code_end: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn peek(&mut self) -> Option<&Token<'a>> {
+        let scanner = &mut self.scanner;
+        self.peeked.get_or_insert_with(|| scanner.next()).as_ref()
+    }
+
+    fn next_if(&mut self, matches: impl FnOnce(&Token<'a>) -> bool) -> Option<Token<'a>> {
+        if matches(self.peek()?) {
+            self.next()
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = match self.peeked.take() {
+            Some(peeked) => peeked,
+            None => self.scanner.next(),
+        }?;
+
+        self.previous_end = token.span.end;
+        match token.token_type {
+            TokenType::Newline(_)
+            | TokenType::Comment(_)
+            | TokenType::MultilineComment(_)
+            | TokenType::RegionBegin(_)
+            | TokenType::RegionEnd(_)
+            | TokenType::Then => {}
+            _ => self.code_end = token.span.end,
+        }
+        Some(token)
+    }
 }
 
 impl<'a> Parser<'a> {
     pub fn new(input: &'a str) -> Parser<'a> {
+        Parser::with_scanner(Scanner::new(input))
+    }
+
+    fn with_scanner(scanner: Scanner<'a>) -> Parser<'a> {
         Parser {
             ast: Vec::new(),
-            scanner: Scanner::new(input).into_iter().peekable(),
+            scanner: Tokens {
+                scanner,
+                peeked: None,
+                previous_end: 0,
+                code_end: 0,
+            },
             allow_unidentified: false,
             can_pair: true,
             leftover_stmts: Vec::new(),
             check_leftovers: false,
+            depth: 0,
+            deepest: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
         }
     }
 
+    /// Makes code nested more than `max_depth` levels deep an error, rather than a stack
+    /// overflow. The default fits in a 2 MB thread stack, even in a debug build. Deeper
+    /// limits need bigger stacks.
+    pub fn max_depth(mut self, max_depth: usize) -> Parser<'a> {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Parses the whole source, failing on the first thing which isn't GML.
     pub fn build_ast(mut self) -> AnyResult<Vec<StmtBox<'a>>> {
         while let Some(_) = self.scanner.peek() {
             self.can_pair = true;
@@ -43,76 +147,87 @@ impl<'a> Parser<'a> {
     }
 
     fn statement(&mut self) -> AnyResult<StmtBox<'a>> {
+        self.nested(|parser| parser.spanned_statement(Parser::any_statement))
+    }
+
+    // Parses a statement with `parse`, and gives it the span of everything it took.
+    fn spanned_statement(&mut self, parse: fn(&mut Parser<'a>) -> AnyResult<StmtBox<'a>>) -> AnyResult<StmtBox<'a>> {
+        let start = self.next_start();
+        let mut statement = parse(self)?;
+        statement.span = self.span_from(start);
+        Ok(statement)
+    }
+
+    fn any_statement(&mut self) -> AnyResult<StmtBox<'a>> {
         if let Some(token) = self.scanner.peek() {
             match token.token_type {
                 TokenType::Comment(_) => {
-                    let comment = self.consume_next();
+                    let comment = self.consume_next()?;
                     return Ok(StatementWrapper::new(Statement::Comment { comment }, false));
                 }
                 TokenType::MultilineComment(_) => {
-                    let multiline_comment = self.consume_next();
+                    let multiline_comment = self.consume_next()?;
                     return Ok(StatementWrapper::new(
                         Statement::MultilineComment { multiline_comment },
                         false,
                     ));
                 }
                 TokenType::RegionBegin(_) => {
-                    let token = self.consume_next();
+                    let token = self.consume_next()?;
                     return Ok(StatementWrapper::new(Statement::RegionBegin(token), false));
                 }
                 TokenType::RegionEnd(_) => {
-                    let token = self.consume_next();
+                    let token = self.consume_next()?;
                     return Ok(StatementWrapper::new(Statement::RegionEnd(token), false));
                 }
                 TokenType::Macro(_) => {
-                    let token = self.consume_next();
-                    return Ok(StatementWrapper::new(Statement::Macro(token), false));
+                    return self.macro_statement();
                 }
                 TokenType::Define => {
-                    self.consume_next();
+                    self.consume_next()?;
                     return self.define_statement();
                 }
                 TokenType::Var | TokenType::GlobalVar => {
                     return self.series_var_declaration();
                 }
                 TokenType::Enum => {
-                    self.consume_next();
+                    self.consume_next()?;
                     return self.enum_declaration();
                 }
                 TokenType::If => {
-                    self.consume_next();
+                    self.consume_next()?;
                     return self.if_statement();
                 }
                 TokenType::Return => {
-                    self.consume_next();
+                    self.consume_next()?;
                     return self.return_statement();
                 }
                 TokenType::Break => {
-                    self.consume_next();
+                    self.consume_next()?;
                     return self.break_statement();
                 }
                 TokenType::Exit => {
-                    self.consume_next();
+                    self.consume_next()?;
                     return self.exit_statment();
                 }
                 TokenType::Do => {
-                    self.consume_next();
+                    self.consume_next()?;
                     return self.do_until_statement();
                 }
                 TokenType::While | TokenType::With | TokenType::Repeat => {
-                    let token = self.consume_next();
+                    let token = self.consume_next()?;
                     return self.while_with_repeat(token);
                 }
                 TokenType::Switch => {
-                    self.consume_next();
+                    self.consume_next()?;
                     return self.switch_statement();
                 }
                 TokenType::For => {
-                    self.consume_next();
+                    self.consume_next()?;
                     return self.for_statement();
                 }
                 TokenType::LeftBrace => {
-                    self.consume_next();
+                    self.consume_next()?;
                     return self.block();
                 }
                 _ => return self.expression_statement(),
@@ -121,6 +236,117 @@ impl<'a> Parser<'a> {
         self.expression_statement()
     }
 
+    fn macro_statement(&mut self) -> AnyResult<StmtBox<'a>> {
+        let token = self.consume_next()?;
+        let definition = if let TokenType::Macro(text) = token.token_type {
+            Parser::macro_definition(text, &token, self.max_depth.saturating_sub(self.depth))
+        } else {
+            None
+        };
+
+        Ok(StatementWrapper::new(Statement::Macro { token, definition }, false))
+    }
+
+    // A `None` here means we couldn't make sense of the macro, and it will be printed as written.
+    fn macro_definition(text: &'a str, token: &Token<'a>, max_depth: usize) -> Option<MacroDefinition<'a>> {
+        fn is_identifier(word: &str) -> bool {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                _ => false,
+            }
+        }
+
+        let rest = text.strip_prefix("#macro")?.trim_start_matches([' ', '\t']);
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '\\')
+            .unwrap_or(rest.len());
+        let (full_name, body) = rest.split_at(name_end);
+
+        let (configuration, name) = match full_name.find(':') {
+            Some(colon) => (Some(&full_name[..colon]), &full_name[colon + 1..]),
+            None => (None, full_name),
+        };
+        if is_identifier(name) == false || matches!(configuration, Some(c) if is_identifier(c) == false) {
+            return None;
+        }
+
+        // Where the first line of the body is in `text`, so its tokens have the right positions.
+        let first_line = body.split('\n').next().unwrap_or("");
+        let body_start = text.len() - body.len() + first_line.len() - first_line.trim_start().len();
+
+        // Every line but the last ends in a `\`, before a `\n` or `\r\n`.
+        let mut lines: Vec<&'a str> = body.split('\n').map(str::trim).collect();
+        let last = lines.len() - 1;
+        for line in &mut lines[..last] {
+            *line = line.strip_suffix('\\').unwrap_or(line).trim_end();
+        }
+        while lines.len() > 1 && lines.last() == Some(&"") {
+            lines.pop();
+        }
+
+        let body_text = &text[body_start..];
+        let body_scanner = || {
+            Scanner::starting_at(
+                body_text,
+                token.span.start + body_start,
+                token.line_number,
+                token.column_number + body_start as u32,
+            )
+            .joining_lines()
+        };
+        let splits_string =
+            || body_scanner().any(|token| matches!(token.token_type, TokenType::String(text) if text.contains('\n')));
+        let body = match Parser::macro_expression(&lines, body_scanner, max_depth) {
+            Some(expression) => MacroBody::Expression(expression),
+            // Lining up the lines would change a string running over them, so it stays as it was.
+            None if splits_string() => MacroBody::Lines(vec![body_text.trim_end()]),
+            None => MacroBody::Lines(lines),
+        };
+
+        Some(MacroDefinition {
+            configuration,
+            name,
+            body,
+        })
+    }
+
+    // A body over several lines keeps its line breaks, but a comment in it could be printed
+    // on another line than it was written on, and a string with a line break in it can't be
+    // lined up, so those bodies stay as they were.
+    fn macro_expression(lines: &[&'a str], scanner: impl Fn() -> Scanner<'a>, max_depth: usize) -> Option<ExprBox<'a>> {
+        if lines.iter().all(|line| line.is_empty()) {
+            return None;
+        }
+        if lines.len() > 1
+            && scanner().any(|token| match token.token_type {
+                TokenType::Comment(_) | TokenType::MultilineComment(_) => true,
+                TokenType::String(text) => text.contains('\n'),
+                _ => false,
+            })
+        {
+            return None;
+        }
+
+        let mut ast = Parser::with_scanner(scanner()).max_depth(max_depth).build_ast().ok()?;
+        if ast.len() != 1 {
+            return None;
+        }
+
+        let stmt = ast.pop()?;
+        if stmt.has_semicolon {
+            return None;
+        }
+
+        match stmt.statement {
+            Statement::ExpressionStatement { expression } => match expression.expr {
+                Expr::UnidentifiedAsLiteral { .. } => None,
+                _ => Some(expression),
+            },
+            _ => None,
+        }
+    }
+
     fn define_statement(&mut self) -> AnyResult<StmtBox<'a>> {
         let comments_after_control_word = self.get_newlines_and_comments();
         let script_name = self.expression()?;
@@ -149,7 +375,7 @@ impl<'a> Parser<'a> {
     }
 
     fn series_var_declaration(&mut self) -> AnyResult<StmtBox<'a>> {
-        let starting_var_type = self.scanner.next().unwrap();
+        let starting_var_type = self.consume_next()?;
         let comments_after_control_word = self.get_newlines_and_comments();
         let var_decl = self.var_declaration()?;
         let has_semicolon = self.check_next_consume(TokenType::Semicolon);
@@ -180,7 +406,7 @@ impl<'a> Parser<'a> {
             let mut say_var_comments = None;
 
             if has_var {
-                say_var = Some(self.scanner.next().unwrap());
+                say_var = Some(self.consume_next()?);
                 say_var_comments = Some(self.get_newlines_and_comments());
             }
 
@@ -213,10 +439,11 @@ impl<'a> Parser<'a> {
                     // Ah shit you suck.
                     let has_semicolon = self.check_next_consume(TokenType::Semicolon);
                     self.check_leftovers = true;
-                    self.leftover_stmts.push(StatementWrapper::new(
-                        Statement::ExpresssionStatement { expression: var_expr },
-                        has_semicolon,
-                    ));
+                    let start = var_expr.span.start;
+                    let mut leftover =
+                        StatementWrapper::new(Statement::ExpressionStatement { expression: var_expr }, has_semicolon);
+                    leftover.span = self.span_from(start);
+                    self.leftover_stmts.push(leftover);
                     end_delimiter = true; // we never woulda gotten here if not for you cursed end delimiters!
                     break;
                 }
@@ -276,7 +503,9 @@ impl<'a> Parser<'a> {
         let condition = self.expression()?;
         let then_branch = self.statement()?;
         let comments_between = self.get_newlines_and_comments();
+        let mut comments_after_else = None;
         let else_branch = if self.check_next_consume(TokenType::Else) {
+            comments_after_else = self.get_newlines_and_comments();
             Some(self.statement()?)
         } else {
             None
@@ -289,6 +518,7 @@ impl<'a> Parser<'a> {
                 condition,
                 then_branch,
                 comments_between,
+                comments_after_else,
                 else_branch,
             },
             has_semicolon,
@@ -342,7 +572,7 @@ impl<'a> Parser<'a> {
         while let Some(token) = self.scanner.peek() {
             match token.token_type {
                 TokenType::Case => {
-                    self.consume_next();
+                    self.consume_next()?;
                     let comments_after_control_word = self.get_newlines_and_comments();
                     let constant = self.expression()?;
                     self.check_next_consume(TokenType::Colon);
@@ -372,7 +602,7 @@ impl<'a> Parser<'a> {
                 }
 
                 TokenType::DefaultCase => {
-                    self.consume_next();
+                    self.consume_next()?;
                     let comments_after_control_word = self.get_newlines_and_comments();
                     self.check_next_consume(TokenType::Colon);
                     let comments_after_colon = self.get_newlines_and_comments();
@@ -400,7 +630,8 @@ impl<'a> Parser<'a> {
                 TokenType::RightBrace => break,
 
                 _ => {
-                    anyhow::bail!("Unknown token {} in Switch statement", token);
+                    let message = format!("`{}` in a switch statement", Printer::get_token_name(&token.token_type));
+                    return Err(Parser::error(token.span, message));
                 }
             }
         }
@@ -429,9 +660,9 @@ impl<'a> Parser<'a> {
         let initializer = if self.check_next_consume(TokenType::Semicolon) {
             None
         } else if self.check_next(TokenType::Var) {
-            Some(self.series_var_declaration()?)
+            Some(self.spanned_statement(Parser::series_var_declaration)?)
         } else {
-            Some(self.expression_statement()?)
+            Some(self.spanned_statement(Parser::expression_statement)?)
         };
         let comments_after_initializer = self.get_newlines_and_comments();
 
@@ -523,43 +754,51 @@ impl<'a> Parser<'a> {
         let has_semicolon = self.check_next_consume(TokenType::Semicolon);
 
         Ok(StatementWrapper::new(
-            Statement::ExpresssionStatement { expression: expr },
+            Statement::ExpressionStatement { expression: expr },
             has_semicolon,
         ))
     }
 
     fn expression(&mut self) -> AnyResult<ExprBox<'a>> {
-        self.allow_unidentified = true;
-        let ret = self.assignment()?;
-        self.can_pair = true;
-        self.allow_unidentified = false;
+        self.nested_by(EXPRESSION_LEVELS, |parser| {
+            parser.allow_unidentified = true;
+            let ret = parser.assignment()?;
+            parser.can_pair = true;
+            parser.allow_unidentified = false;
 
-        Ok(ret)
+            Ok(ret)
+        })
     }
 
-    fn function_declaration(&mut self) -> AnyResult<ExprBox<'a>> {
+    fn function_declaration(&mut self, start: usize) -> AnyResult<ExprBox<'a>> {
         let comments_after_control_word = self.get_newlines_and_comments();
         let call = self.expression()?;
         let comments_after_rparen = self.get_newlines_and_comments();
         let is_constructor = self.check_next_consume(TokenType::Constructor);
 
-        Ok(self.create_comment_expr_box(Expr::Function {
-            comments_after_control_word,
-            call,
-            comments_after_rparen,
-            is_constructor,
-        }))
+        Ok(self.create_comment_expr_box(
+            start,
+            Expr::Function {
+                comments_after_control_word,
+                call,
+                comments_after_rparen,
+                is_constructor,
+            },
+        ))
     }
 
     fn struct_operation(&mut self, token: Token<'a>) -> AnyResult<ExprBox<'a>> {
         let comments_before_expression = self.get_newlines_and_comments();
         let expression = self.expression()?;
 
-        Ok(self.create_comment_expr_box(Expr::StructOperator {
-            token,
-            comments_before_expression,
-            expression,
-        }))
+        Ok(self.create_comment_expr_box(
+            token.span.start,
+            Expr::StructOperator {
+                token,
+                comments_before_expression,
+                expression,
+            },
+        ))
     }
 
     fn assignment(&mut self) -> AnyResult<ExprBox<'a>> {
@@ -568,7 +807,7 @@ impl<'a> Parser<'a> {
         if let Expr::UnidentifiedAsLiteral { literal_token } = expr.expr {
             match literal_token.token_type {
                 TokenType::Function => {
-                    expr = self.function_declaration()?;
+                    expr = self.function_declaration(expr.span.start)?;
                 }
                 TokenType::New | TokenType::Delete => {
                     expr = self.struct_operation(literal_token)?;
@@ -589,17 +828,20 @@ impl<'a> Parser<'a> {
                     | TokenType::BitOrEquals
                     | TokenType::BitAndEquals
                     | TokenType::ModEquals => {
-                        let operator = self.scanner.next().unwrap();
+                        let operator = self.consume_next()?;
                         let comments_and_newlines_between_op_and_r = self.get_newlines_and_comments();
 
-                        let assignment_expr = self.assignment()?;
-
-                        expr = self.create_expr_box_no_comment(Expr::Assign {
-                            left: expr,
-                            operator: operator,
-                            comments_and_newlines_between_op_and_r,
-                            right: assignment_expr,
-                        });
+                        let assignment_expr = self.nested(Parser::assignment)?;
+
+                        expr = self.create_expr_box_no_comment(
+                            expr.span.start,
+                            Expr::Assign {
+                                left: expr,
+                                operator: operator,
+                                comments_and_newlines_between_op_and_r,
+                                right: assignment_expr,
+                            },
+                        );
                     }
                     _ => {}
                 }
@@ -614,18 +856,21 @@ impl<'a> Parser<'a> {
 
         if self.check_next_consume(TokenType::Hook) {
             let comments_and_newlines_after_q = self.get_newlines_and_comments();
-            let left = self.ternary()?;
+            let left = self.nested(Parser::ternary)?;
             self.check_next_consume(TokenType::Colon);
             let comments_and_newlines_after_colon = self.get_newlines_and_comments();
-            let right = self.ternary()?;
-
-            expr = self.create_expr_box_no_comment(Expr::Ternary {
-                conditional: expr,
-                comments_and_newlines_after_q,
-                left,
-                comments_and_newlines_after_colon,
-                right,
-            });
+            let right = self.nested(Parser::ternary)?;
+
+            expr = self.create_expr_box_no_comment(
+                expr.span.start,
+                Expr::Ternary {
+                    conditional: expr,
+                    comments_and_newlines_after_q,
+                    left,
+                    comments_and_newlines_after_colon,
+                    right,
+                },
+            );
         }
 
         Ok(expr)
@@ -635,17 +880,20 @@ impl<'a> Parser<'a> {
     fn or(&mut self) -> AnyResult<ExprBox<'a>> {
         let mut left = self.and()?;
 
-        if self.check_next_either(TokenType::LogicalOr, TokenType::OrAlias) {
-            let token = self.scanner.next().unwrap();
+        while self.check_next_either(TokenType::LogicalOr, TokenType::OrAlias) {
+            let token = self.consume_next()?;
             let comments_and_newlines_between_op_and_r = self.get_newlines_and_comments();
-            let right = self.or()?;
+            let right = self.and()?;
 
-            left = self.create_expr_box_no_comment(Expr::Binary {
-                left,
-                operator: token,
-                comments_and_newlines_between_op_and_r,
-                right,
-            });
+            left = self.create_expr_box_no_comment(
+                left.span.start,
+                Expr::Binary {
+                    left,
+                    operator: token,
+                    comments_and_newlines_between_op_and_r,
+                    right,
+                },
+            );
         }
 
         Ok(left)
@@ -654,17 +902,20 @@ impl<'a> Parser<'a> {
     fn and(&mut self) -> AnyResult<ExprBox<'a>> {
         let mut left = self.xor()?;
 
-        if self.check_next_either(TokenType::LogicalAnd, TokenType::AndAlias) {
-            let token = self.scanner.next().unwrap();
+        while self.check_next_either(TokenType::LogicalAnd, TokenType::AndAlias) {
+            let token = self.consume_next()?;
             let comments_and_newlines_between_op_and_r = self.get_newlines_and_comments();
-            let right = self.and()?;
+            let right = self.xor()?;
 
-            left = self.create_expr_box_no_comment(Expr::Binary {
-                left,
-                operator: token,
-                comments_and_newlines_between_op_and_r,
-                right,
-            });
+            left = self.create_expr_box_no_comment(
+                left.span.start,
+                Expr::Binary {
+                    left,
+                    operator: token,
+                    comments_and_newlines_between_op_and_r,
+                    right,
+                },
+            );
         }
 
         Ok(left)
@@ -673,17 +924,20 @@ impl<'a> Parser<'a> {
     fn xor(&mut self) -> AnyResult<ExprBox<'a>> {
         let mut left = self.equality()?;
 
-        if self.check_next_either(TokenType::LogicalXor, TokenType::XorAlias) {
-            let token = self.scanner.next().unwrap();
+        while self.check_next_either(TokenType::LogicalXor, TokenType::XorAlias) {
+            let token = self.consume_next()?;
             let comments_and_newlines_between_op_and_r = self.get_newlines_and_comments();
-            let right = self.xor()?;
-
-            left = self.create_expr_box_no_comment(Expr::Binary {
-                left,
-                operator: token,
-                comments_and_newlines_between_op_and_r,
-                right,
-            })
+            let right = self.equality()?;
+
+            left = self.create_expr_box_no_comment(
+                left.span.start,
+                Expr::Binary {
+                    left,
+                    operator: token,
+                    comments_and_newlines_between_op_and_r,
+                    right,
+                },
+            )
         }
 
         Ok(left)
@@ -694,17 +948,23 @@ impl<'a> Parser<'a> {
 
         if self.can_pair {
             while let Some(t) = self.scanner.peek() {
-                if t.token_type == TokenType::EqualEqual || t.token_type == TokenType::BangEqual {
-                    let token = self.scanner.next().unwrap();
+                if t.token_type == TokenType::EqualEqual
+                    || t.token_type == TokenType::BangEqual
+                    || t.token_type == TokenType::LessThanGreaterThan
+                {
+                    let token = self.consume_next()?;
                     let comments_and_newlines_between_op_and_r = self.get_newlines_and_comments();
                     let right = self.comparison()?;
 
-                    expr = self.create_expr_box_no_comment(Expr::Binary {
-                        left: expr,
-                        operator: token,
-                        comments_and_newlines_between_op_and_r,
-                        right,
-                    });
+                    expr = self.create_expr_box_no_comment(
+                        expr.span.start,
+                        Expr::Binary {
+                            left: expr,
+                            operator: token,
+                            comments_and_newlines_between_op_and_r,
+                            right,
+                        },
+                    );
                 } else {
                     break;
                 }
@@ -721,16 +981,19 @@ impl<'a> Parser<'a> {
             while let Some(t) = self.scanner.peek() {
                 match t.token_type {
                     TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
-                        let t = self.scanner.next().unwrap();
+                        let t = self.consume_next()?;
                         let comments_and_newlines_between_op_and_r = self.get_newlines_and_comments();
                         let right = self.binary()?;
 
-                        expr = self.create_expr_box_no_comment(Expr::Binary {
-                            left: expr,
-                            operator: t,
-                            comments_and_newlines_between_op_and_r,
-                            right,
-                        });
+                        expr = self.create_expr_box_no_comment(
+                            expr.span.start,
+                            Expr::Binary {
+                                left: expr,
+                                operator: t,
+                                comments_and_newlines_between_op_and_r,
+                                right,
+                            },
+                        );
                     }
                     _ => break,
                 };
@@ -747,16 +1010,19 @@ impl<'a> Parser<'a> {
             while let Some(t) = self.scanner.peek() {
                 match t.token_type {
                     TokenType::BitAnd | TokenType::BitOr | TokenType::BitXor => {
-                        let t = self.scanner.next().unwrap();
+                        let t = self.consume_next()?;
                         let comments_and_newlines_between_op_and_r = self.get_newlines_and_comments();
                         let right = self.bitshift()?;
 
-                        expr = self.create_expr_box_no_comment(Expr::Binary {
-                            left: expr,
-                            operator: t,
-                            comments_and_newlines_between_op_and_r,
-                            right,
-                        });
+                        expr = self.create_expr_box_no_comment(
+                            expr.span.start,
+                            Expr::Binary {
+                                left: expr,
+                                operator: t,
+                                comments_and_newlines_between_op_and_r,
+                                right,
+                            },
+                        );
                     }
                     _ => break,
                 }
@@ -773,16 +1039,19 @@ impl<'a> Parser<'a> {
             while let Some(t) = self.scanner.peek() {
                 match t.token_type {
                     TokenType::BitLeft | TokenType::BitRight => {
-                        let t = self.scanner.next().unwrap();
+                        let t = self.consume_next()?;
                         let comments_and_newlines_between_op_and_r = self.get_newlines_and_comments();
                         let right = self.addition()?;
 
-                        expr = self.create_expr_box_no_comment(Expr::Binary {
-                            left: expr,
-                            operator: t,
-                            comments_and_newlines_between_op_and_r,
-                            right,
-                        });
+                        expr = self.create_expr_box_no_comment(
+                            expr.span.start,
+                            Expr::Binary {
+                                left: expr,
+                                operator: t,
+                                comments_and_newlines_between_op_and_r,
+                                right,
+                            },
+                        );
                     }
                     _ => break,
                 }
@@ -799,16 +1068,19 @@ impl<'a> Parser<'a> {
             while let Some(t) = self.scanner.peek() {
                 match t.token_type {
                     TokenType::Minus | TokenType::Plus => {
-                        let token = self.scanner.next().unwrap();
+                        let token = self.consume_next()?;
                         let comments_and_newlines_between_op_and_r = self.get_newlines_and_comments();
                         let right = self.multiplication()?;
 
-                        expr = self.create_expr_box_no_comment(Expr::Binary {
-                            left: expr,
-                            operator: token,
-                            comments_and_newlines_between_op_and_r,
-                            right,
-                        });
+                        expr = self.create_expr_box_no_comment(
+                            expr.span.start,
+                            Expr::Binary {
+                                left: expr,
+                                operator: token,
+                                comments_and_newlines_between_op_and_r,
+                                right,
+                            },
+                        );
                     }
                     _ => break,
                 };
@@ -825,16 +1097,19 @@ impl<'a> Parser<'a> {
             while let Some(t) = self.scanner.peek() {
                 match t.token_type {
                     TokenType::Slash | TokenType::Star | TokenType::Mod | TokenType::ModAlias | TokenType::Div => {
-                        let token = self.scanner.next().unwrap();
+                        let token = self.consume_next()?;
                         let comments_and_newlines_between_op_and_r = self.get_newlines_and_comments();
                         let right = self.unary()?;
 
-                        expr = self.create_expr_box_no_comment(Expr::Binary {
-                            left: expr,
-                            operator: token,
-                            comments_and_newlines_between_op_and_r,
-                            right,
-                        });
+                        expr = self.create_expr_box_no_comment(
+                            expr.span.start,
+                            Expr::Binary {
+                                left: expr,
+                                operator: token,
+                                comments_and_newlines_between_op_and_r,
+                                right,
+                            },
+                        );
                     }
                     _ => break,
                 };
@@ -849,27 +1124,33 @@ impl<'a> Parser<'a> {
             if let Some(t) = self.scanner.peek() {
                 match t.token_type {
                     TokenType::Bang | TokenType::Minus | TokenType::Plus | TokenType::Tilde | TokenType::NotAlias => {
-                        let t = self.scanner.next().unwrap();
+                        let t = self.consume_next()?;
                         let comments_and_newlines_between = self.get_newlines_and_comments();
-                        let right = self.unary()?;
-
-                        return Ok(self.create_expr_box_no_comment(Expr::Unary {
-                            operator: t,
-                            comments_and_newlines_between,
-                            right,
-                        }));
+                        let right = self.nested(Parser::unary)?;
+
+                        return Ok(self.create_expr_box_no_comment(
+                            t.span.start,
+                            Expr::Unary {
+                                operator: t,
+                                comments_and_newlines_between,
+                                right,
+                            },
+                        ));
                     }
 
                     TokenType::Incrementer | TokenType::Decrementer => {
-                        let t = self.scanner.next().unwrap();
+                        let t = self.consume_next()?;
                         let comments_and_newlines_between = self.get_newlines_and_comments();
-                        let right = self.unary()?;
-
-                        return Ok(self.create_expr_box_no_comment(Expr::Unary {
-                            operator: t,
-                            comments_and_newlines_between,
-                            right,
-                        }));
+                        let right = self.nested(Parser::unary)?;
+
+                        return Ok(self.create_expr_box_no_comment(
+                            t.span.start,
+                            Expr::Unary {
+                                operator: t,
+                                comments_and_newlines_between,
+                                right,
+                            },
+                        ));
                     }
 
                     _ => {}
@@ -884,44 +1165,57 @@ impl<'a> Parser<'a> {
         let mut expr = self.call()?;
 
         if self.check_next_either(TokenType::Incrementer, TokenType::Decrementer) {
-            let t = self.scanner.next().unwrap();
+            let t = self.consume_next()?;
 
             let comments_and_newlines_between = self.get_newlines_and_comments();
-            expr = self.create_expr_box_no_comment(Expr::Postfix {
-                operator: t,
-                comments_and_newlines_between,
-                expr,
-            });
+            expr = self.create_expr_box_no_comment(
+                expr.span.start,
+                Expr::Postfix {
+                    operator: t,
+                    comments_and_newlines_between,
+                    expr,
+                },
+            );
         }
 
         Ok(expr)
     }
 
     fn call(&mut self) -> AnyResult<ExprBox<'a>> {
+        let chain = self.start_chain();
+        let mut links = 0;
         let mut expression = self.primary()?;
 
         if self.check_next_consume(TokenType::LeftParen) {
             let comments_and_newlines_after_lparen = self.get_newlines_and_comments();
             let arguments = self.finish_call(TokenType::RightParen, TokenType::Comma)?;
 
-            expression = self.create_comment_expr_box(Expr::Call {
-                procedure_name: expression,
-                arguments,
-                comments_and_newlines_after_lparen,
-            });
+            links += 1;
+            expression = self.create_comment_expr_box(
+                expression.span.start,
+                Expr::Call {
+                    procedure_name: expression,
+                    arguments,
+                    comments_and_newlines_after_lparen,
+                },
+            );
         }
 
         while let Some(token) = self.scanner.peek() {
             match token.token_type {
                 TokenType::Dot => {
-                    self.consume_next();
+                    self.consume_next()?;
                     let comments_between = self.get_newlines_and_comments();
-                    let instance_variable = self.call()?;
-                    expression = self.create_comment_expr_box(Expr::DotAccess {
-                        object_name: expression,
-                        comments_between,
-                        instance_variable,
-                    });
+                    let instance_variable = self.nested(Parser::call)?;
+                    links += 1;
+                    expression = self.create_comment_expr_box(
+                        expression.span.start,
+                        Expr::DotAccess {
+                            object_name: expression,
+                            comments_between,
+                            instance_variable,
+                        },
+                    );
                 }
 
                 TokenType::LeftBracket
@@ -929,7 +1223,7 @@ impl<'a> Parser<'a> {
                 | TokenType::MapIndexer
                 | TokenType::ListIndexer
                 | TokenType::GridIndexer => {
-                    let access_type = self.scanner.next().unwrap();
+                    let access_type = self.consume_next()?;
                     let mut access_exprs = vec![];
 
                     while let Some(token) = self.scanner.peek() {
@@ -945,17 +1239,22 @@ impl<'a> Parser<'a> {
                     }
 
                     self.check_next_consume(TokenType::RightBracket);
-                    expression = self.create_comment_expr_box(Expr::DataStructureAccess {
-                        ds_name: expression,
-                        access_type,
-                        access_exprs,
-                    });
+                    links += 1;
+                    expression = self.create_comment_expr_box(
+                        expression.span.start,
+                        Expr::DataStructureAccess {
+                            ds_name: expression,
+                            access_type,
+                            access_exprs,
+                        },
+                    );
                 }
 
                 _ => break,
             }
         }
 
+        self.end_chain(chain, links)?;
         Ok(expression)
     }
 
@@ -963,36 +1262,45 @@ impl<'a> Parser<'a> {
         if let Some(t) = self.scanner.peek() {
             let output = match t.token_type {
                 TokenType::Number(_) | TokenType::String(_) => {
-                    let t = self.consume_next();
+                    let t = self.consume_next()?;
                     let comments = self.get_newlines_and_comments();
-                    self.create_expr_box_no_comment(Expr::Literal {
-                        literal_token: t,
-                        comments,
-                    })
+                    self.create_expr_box_no_comment(
+                        t.span.start,
+                        Expr::Literal {
+                            literal_token: t,
+                            comments,
+                        },
+                    )
                 }
                 TokenType::NumberStartDot(_) => {
-                    let t = self.consume_next();
+                    let t = self.consume_next()?;
                     let comments = self.get_newlines_and_comments();
-                    self.create_expr_box_no_comment(Expr::NumberStartDot {
-                        literal_token: t,
-                        comments,
-                    })
+                    self.create_expr_box_no_comment(
+                        t.span.start,
+                        Expr::NumberStartDot {
+                            literal_token: t,
+                            comments,
+                        },
+                    )
                 }
                 TokenType::NumberEndDot(_) => {
-                    let t = self.consume_next();
+                    let t = self.consume_next()?;
                     let comments = self.get_newlines_and_comments();
-                    self.create_expr_box_no_comment(Expr::NumberEndDot {
-                        literal_token: t,
-                        comments,
-                    })
+                    self.create_expr_box_no_comment(
+                        t.span.start,
+                        Expr::NumberEndDot {
+                            literal_token: t,
+                            comments,
+                        },
+                    )
                 }
                 TokenType::Identifier(_) => {
-                    let t = self.consume_next();
+                    let t = self.consume_next()?;
                     let comments = self.get_newlines_and_comments();
-                    self.create_expr_box_no_comment(Expr::Identifier { name: t, comments })
+                    self.create_expr_box_no_comment(t.span.start, Expr::Identifier { name: t, comments })
                 }
                 TokenType::LeftParen => {
-                    self.consume_next();
+                    let lparen = self.consume_next()?;
                     let comments_and_newlines_after_lparen = self.get_newlines_and_comments();
 
                     let mut expressions = vec![];
@@ -1003,53 +1311,66 @@ impl<'a> Parser<'a> {
 
                     let comments_and_newlines_after_rparen = self.get_newlines_and_comments();
 
-                    self.create_expr_box_no_comment(Expr::Grouping {
-                        expressions,
-                        comments_and_newlines_after_lparen,
-                        comments_and_newlines_after_rparen,
-                    })
+                    self.create_expr_box_no_comment(
+                        lparen.span.start,
+                        Expr::Grouping {
+                            expressions,
+                            comments_and_newlines_after_lparen,
+                            comments_and_newlines_after_rparen,
+                        },
+                    )
                 }
 
                 TokenType::LeftBracket => {
-                    self.consume_next();
+                    let lbracket = self.consume_next()?;
                     let comments_and_newlines_after_lbracket = self.get_newlines_and_comments();
                     let arguments = self.finish_call(TokenType::RightBracket, TokenType::Comma)?;
 
-                    self.create_expr_box_no_comment(Expr::ArrayLiteral {
-                        comments_and_newlines_after_lbracket,
-                        arguments,
-                    })
+                    self.create_expr_box_no_comment(
+                        lbracket.span.start,
+                        Expr::ArrayLiteral {
+                            comments_and_newlines_after_lbracket,
+                            arguments,
+                        },
+                    )
                 }
 
                 TokenType::Newline(_) => {
-                    self.consume_next();
+                    let newline = self.consume_next()?;
                     self.can_pair = false;
-                    self.create_expr_box_no_comment(Expr::Newline)
+                    self.create_expr_box_no_comment(newline.span.start, Expr::Newline)
                 }
                 TokenType::Comment(_) => {
-                    let comment = self.consume_next();
+                    let comment = self.consume_next()?;
                     self.can_pair = false;
-                    self.create_expr_box_no_comment(Expr::Comment { comment })
+                    self.create_expr_box_no_comment(comment.span.start, Expr::Comment { comment })
                 }
                 TokenType::MultilineComment(_) => {
-                    let multiline_comment = self.consume_next();
+                    let multiline_comment = self.consume_next()?;
                     self.can_pair = false;
-                    self.create_expr_box_no_comment(Expr::MultilineComment { multiline_comment })
+                    self.create_expr_box_no_comment(
+                        multiline_comment.span.start,
+                        Expr::MultilineComment { multiline_comment },
+                    )
                 }
                 _ => {
-                    let literal_token = self.consume_next();
+                    let literal_token = self.consume_next()?;
                     if self.allow_unidentified == false {
-                        anyhow::bail!("Error parsing {}", literal_token);
+                        let message = format!("unexpected `{}`", Printer::get_token_name(&literal_token.token_type));
+                        return Err(Parser::error(literal_token.span, message));
                     }
 
-                    self.create_comment_expr_box(Expr::UnidentifiedAsLiteral { literal_token })
+                    self.create_comment_expr_box(
+                        literal_token.span.start,
+                        Expr::UnidentifiedAsLiteral { literal_token },
+                    )
                 }
             };
 
             return Ok(output);
         }
 
-        anyhow::bail!("Unexpected end!");
+        Err(self.unexpected_end())
     }
 
     fn finish_call(
@@ -1121,7 +1442,7 @@ impl<'a> Parser<'a> {
         }
 
         if self.check_next(token_type) {
-            self.consume_next();
+            self.scanner.next();
             true
         } else {
             false
@@ -1129,43 +1450,129 @@ impl<'a> Parser<'a> {
     }
     fn get_newlines_and_comments(&mut self) -> Option<Vec<Token<'a>>> {
         let mut ret: Option<Vec<Token<'a>>> = None;
-        while let Some(token) = self.scanner.peek() {
-            match token.token_type {
+        while let Some(token) = self.scanner.next_if(|token| {
+            matches!(
+                token.token_type,
                 TokenType::Newline(_)
-                | TokenType::Comment(_)
-                | TokenType::MultilineComment(_)
-                | TokenType::RegionBegin(_)
-                | TokenType::RegionEnd(_)
-                | TokenType::Then => {
-                    let token = self.scanner.next().unwrap();
-                    if let Some(vec) = &mut ret {
-                        vec.push(token);
-                    } else {
-                        ret = Some(vec![token]);
-                    }
-                }
-                _ => break,
+                    | TokenType::Comment(_)
+                    | TokenType::MultilineComment(_)
+                    | TokenType::RegionBegin(_)
+                    | TokenType::RegionEnd(_)
+                    | TokenType::Then
+            )
+        }) {
+            if let Some(vec) = &mut ret {
+                vec.push(token);
+            } else {
+                ret = Some(vec![token]);
             }
         }
 
         ret
     }
 
-    fn consume_next(&mut self) -> Token<'a> {
-        self.scanner.next().unwrap()
+    // Parses with `parse` a level deeper.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Parser<'a>) -> AnyResult<T>) -> AnyResult<T> {
+        self.nested_by(1, parse)
+    }
+
+    // Parsing, printing and dropping the tree all recurse for every level of nesting, so
+    // code nested too deep would run out of stack. It is an error instead.
+    fn nested_by<T>(&mut self, levels: usize, parse: impl FnOnce(&mut Parser<'a>) -> AnyResult<T>) -> AnyResult<T> {
+        let depth = self.depth;
+        self.depth += levels;
+        self.deepest = self.deepest.max(self.depth);
+        if self.depth > self.max_depth {
+            return Err(self.too_deep());
+        }
+
+        let result = parse(self);
+        self.depth = depth;
+        result
+    }
+
+    // A chain like `a[b].c(d)` is parsed in a loop, but its tree is as deep as it is long,
+    // with the first link at the bottom, and printing it recurses all the way down. So its
+    // links are counted on top of the deepest level anything in the chain reached. Gives
+    // back that level from before. Chains of operators are walked in loops everywhere, so
+    // they aren't counted.
+    fn start_chain(&mut self) -> usize {
+        std::mem::replace(&mut self.deepest, self.depth)
+    }
+
+    fn end_chain(&mut self, deepest_before: usize, links: usize) -> AnyResult<()> {
+        let deepest = self.deepest + links / LINKS_PER_LEVEL;
+        self.deepest = deepest_before.max(deepest);
+        if deepest > self.max_depth {
+            return Err(self.too_deep());
+        }
+        Ok(())
+    }
+
+    fn too_deep(&mut self) -> anyhow::Error {
+        let message = format!(
+            "code is nested more than {} levels deep. Raise `max_depth` to format it.",
+            self.max_depth
+        );
+        let span = match self.scanner.peek() {
+            Some(token) => token.span,
+            None => self.end_span(),
+        };
+        Parser::error(span, message)
+    }
+
+    fn consume_next(&mut self) -> AnyResult<Token<'a>> {
+        match self.scanner.next() {
+            Some(token) => Ok(token),
+            None => Err(self.unexpected_end()),
+        }
+    }
+
+    fn error(span: Span, message: String) -> anyhow::Error {
+        ParseError { message, span }.into()
+    }
+
+    fn unexpected_end(&self) -> anyhow::Error {
+        Parser::error(self.end_span(), "unexpected end of file".to_owned())
     }
 
-    fn create_comment_expr_box(&mut self, expr: Expr<'a>) -> ExprBox<'a> {
+    // Where the tokens given out so far end.
+    fn end_span(&self) -> Span {
+        Span {
+            start: self.scanner.previous_end,
+            end: self.scanner.previous_end,
+        }
+    }
+
+    fn create_comment_expr_box(&mut self, start: usize, expr: Expr<'a>) -> ExprBox<'a> {
+        let span = self.span_from(start);
         Box::new(ExprBoxInterior {
             expr,
             trailing_comments: self.get_newlines_and_comments(),
+            span,
         })
     }
 
-    fn create_expr_box_no_comment(&self, expr: Expr<'a>) -> ExprBox<'a> {
+    fn create_expr_box_no_comment(&self, start: usize, expr: Expr<'a>) -> ExprBox<'a> {
         Box::new(ExprBoxInterior {
             expr,
             trailing_comments: None,
+            span: self.span_from(start),
         })
     }
+
+    fn next_start(&mut self) -> usize {
+        let previous_end = self.scanner.previous_end;
+        self.scanner.peek().map_or(previous_end, |token| token.span.start)
+    }
+
+    // A node ends with the last of its code, unless it is only comments and newlines.
+    fn span_from(&self, start: usize) -> Span {
+        let end = if self.scanner.code_end > start {
+            self.scanner.code_end
+        } else {
+            self.scanner.previous_end
+        };
+        Span { start, end }
+    }
 }
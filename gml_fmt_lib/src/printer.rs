@@ -1,7 +1,8 @@
 use super::expressions::*;
-use super::lex_token::{Token, TokenType};
+use super::lex_token::{Span, Token, TokenType};
+use super::source_map::Mapping;
 use super::statements::*;
-use super::LangConfig;
+use super::{EndOfLine, LangConfig, Semicolons, TrailingCommas};
 use bitflags;
 
 type StmtBox<'a> = Box<StatementWrapper<'a>>;
@@ -18,6 +19,8 @@ const SEMICOLON: &str = ";";
 
 pub struct Printer<'a> {
     output: Vec<&'a str>,
+    // The token each entry of `output` was printed from, if it was printed from one.
+    origins: Vec<Option<&'a Token<'a>>>,
     lang_config: &'a LangConfig,
     indentation: usize,
     do_not_print_single_newline_statement: bool,
@@ -26,13 +29,17 @@ pub struct Printer<'a> {
     user_indentation_instructions: Vec<usize>,
     do_dot_indent: bool,
     in_a_for_loop: Vec<()>,
+    in_a_function_signature: Vec<()>,
     do_not_need_semicolon: Vec<()>,
+    next_statement: Option<&'a StatementWrapper<'a>>,
+    comment_follows: bool,
 }
 
 impl<'a> Printer<'a> {
     pub fn new(size: usize, lang_config: &'a LangConfig) -> Printer<'a> {
         Printer {
             output: Vec::with_capacity(size),
+            origins: Vec::with_capacity(size),
             lang_config,
             indentation: 0,
             do_not_print_single_newline_statement: false,
@@ -41,22 +48,57 @@ impl<'a> Printer<'a> {
             user_indentation_instructions: Vec::new(),
             do_dot_indent: true,
             in_a_for_loop: Vec::new(),
+            in_a_function_signature: Vec::new(),
             do_not_need_semicolon: Vec::new(),
+            next_statement: None,
+            comment_follows: false,
         }
     }
 
-    pub fn get_output(self, size: usize) -> String {
+    /// The output, and where each token in it came from in the source.
+    pub fn get_output(self, size: usize) -> (String, Vec<Mapping>) {
         let mut output = String::with_capacity(size);
+        let mut mappings = Vec::new();
+
+        // `Auto` should be settled by now, so it's treated as `Lf` here.
+        let crlf = self.lang_config.end_of_line == EndOfLine::Crlf;
+        for (this_one, origin) in self.output.into_iter().zip(self.origins) {
+            let start = output.len();
+            // Multiline comments bring their own line breaks, so we convert those too. Strings
+            // keep theirs, since they're part of the string.
+            if let Some(Token {
+                token_type: TokenType::String(_),
+                ..
+            }) = origin
+            {
+                output.push_str(this_one);
+            } else if crlf && this_one.contains('\n') {
+                output.push_str(&this_one.replace("\r\n", "\n").replace('\n', "\r\n"));
+            } else if crlf == false && this_one.contains('\r') {
+                output.push_str(&this_one.replace("\r\n", "\n"));
+            } else {
+                output.push_str(this_one);
+            }
 
-        for this_one in self.output {
-            output.push_str(this_one);
+            // Tokens we made up, rather than read, have nowhere in the source to map to.
+            if let Some(token) = origin.filter(|token| token.span.start < token.span.end) {
+                mappings.push(Mapping::new(
+                    Span {
+                        start,
+                        end: output.len(),
+                    },
+                    token,
+                ));
+            }
         }
 
-        output
+        (output, mappings)
     }
 
     pub fn autoformat(mut self, ast: &'a [StmtBox<'a>]) -> Printer {
-        for this_statement in ast {
+        for (i, this_statement) in ast.iter().enumerate() {
+            self.next_statement = Printer::following_statement(&ast[i + 1..]);
+            self.comment_follows = Printer::comment_follows(&ast[i + 1..]);
             self.print_statement(this_statement);
         }
 
@@ -68,7 +110,7 @@ impl<'a> Printer<'a> {
             loop {
                 match self.output[pos] {
                     SPACE | TAB | NEWLINE => {
-                        self.output.remove(pos);
+                        self.remove_entry(pos);
                         if pos == 0 {
                             break;
                         } else {
@@ -89,6 +131,8 @@ impl<'a> Printer<'a> {
     }
 
     fn print_statement(&mut self, stmt: &'a StatementWrapper<'a>) {
+        let has_semicolon = self.keeps_semicolon(stmt);
+        let comment_follows = std::mem::take(&mut self.comment_follows);
         match &stmt.statement {
             Statement::VariableDeclList {
                 starting_var_type,
@@ -138,12 +182,14 @@ impl<'a> Printer<'a> {
                     if last_line == false {
                         self.print(COMMA, true);
                     } else {
-                        if var_decl_list.has_end_delimiter {
+                        // we never add one here, as it would pull the next statement into the list.
+                        let trailing_comma = self.trailing_comma(var_decl_list.has_end_delimiter, indented_vars, false);
+                        if trailing_comma {
                             self.print(COMMA, true);
                         } else {
                             interrupt_eol_formatting = self.do_not_need_semicolon.len() > 0;
 
-                            if interrupt_eol_formatting == false {
+                            if interrupt_eol_formatting == false && self.adds_semicolons(has_semicolon) {
                                 self.print(SEMICOLON, true);
                             }
                         }
@@ -224,15 +270,16 @@ impl<'a> Printer<'a> {
                     self.print_newline(IndentationMove::Right);
                 }
                 self.backspace();
-                self.print_delimited_lines(members, COMMA, true, true);
+                let trailing_comma = self.trailing_comma(members.has_end_delimiter, true, true);
+                self.print_delimited_lines(members, COMMA, trailing_comma, true, true);
 
                 self.set_indentation(IndentationMove::Left);
                 self.backspace_till_newline();
 
                 self.print(RBRACE, false);
-                self.print_semicolon(stmt.has_semicolon);
+                self.print_semicolon(has_semicolon);
             }
-            Statement::ExpresssionStatement { expression } => {
+            Statement::ExpressionStatement { expression } => {
                 // let final_newlines: Option<&CommentsAndNewlines> = {
                 //     if stmt.has_semicolon {
                 //         None
@@ -259,7 +306,7 @@ impl<'a> Printer<'a> {
                 //         true,
                 //     );
                 // } else {
-                self.print_semicolon(stmt.has_semicolon);
+                self.print_semicolon(has_semicolon);
                 // }
             }
             Statement::Block {
@@ -270,18 +317,16 @@ impl<'a> Printer<'a> {
                     self.ensure_space();
                 }
 
-                let block_instructions = if self.block_instructions.is_empty() {
-                    BlockInstruction::NONE
-                } else {
-                    self.block_instructions.pop().unwrap()
-                };
+                let block_instructions = self.block_instructions.pop().unwrap_or(BlockInstruction::NONE);
 
                 self.print(LBRACE, false);
 
-                // if we have more than one statement, or if our statement isn't an expression statement, then we indent.
+                // if we have more than one statement, or if our statement isn't an expression statement, or ends its
+                // line, then we indent.
                 let must_indent = block_instructions.contains(BlockInstruction::MUST_INDENT)
                     || statements.len() > 1
-                    || (statements.len() == 1 && statements[0].hold_expr() == false);
+                    || (statements.len() == 1
+                        && (statements[0].hold_expr() == false || Printer::ends_line(&statements[0])));
                 let did_move = self.print_comments_and_newlines(
                     comments_after_lbrace,
                     CommentAndNewlinesInstruction::new(IndentationMove::Right, LeadingNewlines::One),
@@ -299,10 +344,15 @@ impl<'a> Printer<'a> {
                     self.do_not_need_semicolon.push(());
                 }
 
-                for stmt in statements {
+                let next_statement = self.next_statement.take();
+                for (i, stmt) in statements.iter().enumerate() {
+                    self.next_statement = Printer::following_statement(&statements[i + 1..]);
+                    let comment_follows = Printer::comment_follows(&statements[i + 1..]);
+                    self.comment_follows = comment_follows;
                     self.print_statement(stmt);
 
-                    if did_newline & stmt.has_semicolon {
+                    // a comment straight after the semicolon belongs on the same line.
+                    if did_newline & stmt.has_semicolon && comment_follows == false {
                         if self.on_whitespace_line() == false {
                             self.print_newline(IndentationMove::Stay);
                             self.do_not_print_single_newline_statement = true;
@@ -310,18 +360,20 @@ impl<'a> Printer<'a> {
                     }
                 }
 
+                self.next_statement = next_statement;
+
                 if did_newline {
                     self.backspace_whitespace();
                     self.print_newline(IndentationMove::Left);
                 } else {
                     self.backspace();
-                    if self.last_entry().unwrap() != "{" {
+                    if self.last_entry() != Some(LBRACE) {
                         self.ensure_space();
                     }
                 }
 
                 self.print(RBRACE, false);
-                self.print_semicolon(stmt.has_semicolon);
+                self.print_semicolon(has_semicolon);
 
                 if block_instructions.contains(BlockInstruction::NO_NEWLINE_AFTER_BLOCK) == false {
                     self.ensure_newline(IndentationMove::Stay);
@@ -334,6 +386,7 @@ impl<'a> Printer<'a> {
                 condition,
                 then_branch,
                 comments_between,
+                comments_after_else,
                 else_branch,
             } => {
                 self.print("if", true);
@@ -361,7 +414,14 @@ impl<'a> Printer<'a> {
                 }
                 self.print_expr(condition);
                 let forcible_indent = self.indentation != current_indentation && has_block == false;
-                self.print_statement(then_branch);
+                if else_branch.is_some() {
+                    // an `else` can't be swallowed by the then branch.
+                    let next_statement = self.next_statement.take();
+                    self.print_statement(then_branch);
+                    self.next_statement = next_statement;
+                } else {
+                    self.print_statement(then_branch);
+                }
 
                 let did_move = self.print_comments_and_newlines(
                     comments_between,
@@ -387,12 +447,35 @@ impl<'a> Printer<'a> {
                 if let Some(else_branch) = else_branch {
                     if forcible_indent == false {
                         self.backspace_whitespace();
+
+                        // pulling the `else` up behind a line comment would comment it out.
+                        if matches!(self.last_entry(), Some(entry) if entry.starts_with("//")) {
+                            self.print_newline(IndentationMove::Stay);
+                        }
                     }
                     self.ensure_space();
                     self.print("else", true);
+
+                    // a block, or an `else if`, pulls itself up onto the `else` line.
+                    let stays_on_line = matches!(else_branch.statement, Statement::Block { .. } | Statement::If { .. });
+                    let did_move = self.print_comments_and_newlines(
+                        comments_after_else,
+                        if stays_on_line {
+                            CommentAndNewlinesInstruction::new(IndentationMove::Stay, LeadingNewlines::One)
+                        } else {
+                            CommentAndNewlinesInstruction::new_respect_users(
+                                IndentationMove::Right,
+                                LeadingNewlines::One,
+                            )
+                        },
+                    );
                     self.print_statement(else_branch);
+
+                    if did_move && stays_on_line == false {
+                        self.set_indentation(IndentationMove::Left);
+                    }
                 }
-                self.print_semicolon(stmt.has_semicolon);
+                self.print_semicolon(has_semicolon);
             }
             Statement::WhileWithRepeat {
                 token,
@@ -409,7 +492,7 @@ impl<'a> Printer<'a> {
                 self.print_expr(condition);
 
                 self.print_statement(body);
-                self.print_semicolon(stmt.has_semicolon);
+                self.print_semicolon(has_semicolon);
             }
             Statement::DoUntil {
                 comments_after_control_word,
@@ -425,14 +508,16 @@ impl<'a> Printer<'a> {
 
                 self.block_instructions
                     .push(BlockInstruction::NO_NEWLINE_AFTER_BLOCK | BlockInstruction::MUST_INDENT);
+                let next_statement = self.next_statement.take();
                 self.print_statement(body);
+                self.next_statement = next_statement;
                 self.print_comments_and_newlines(
                     comments_between,
                     CommentAndNewlinesInstruction::new(IndentationMove::Stay, LeadingNewlines::None),
                 );
 
                 self.backspace_whitespace();
-                if self.last_entry().unwrap() == RBRACE {
+                if self.last_entry() == Some(RBRACE) {
                     self.ensure_space();
                 } else {
                     self.print_newline(IndentationMove::Stay);
@@ -440,7 +525,7 @@ impl<'a> Printer<'a> {
                 self.print("until", true);
                 self.print_expr(condition);
                 self.backspace();
-                self.print_semicolon_and_newline(stmt.has_semicolon, IndentationMove::Stay);
+                self.print_semicolon_and_newline(has_semicolon, IndentationMove::Stay);
             }
             Statement::For {
                 comments_after_control_word,
@@ -514,7 +599,7 @@ impl<'a> Printer<'a> {
                 );
 
                 self.print_statement(body);
-                self.print_semicolon(stmt.has_semicolon);
+                self.print_semicolon(has_semicolon);
             }
             Statement::Return { expression } => {
                 self.print("return", false);
@@ -524,16 +609,16 @@ impl<'a> Printer<'a> {
                     
                     self.print_expr(expression);
                 }
-                self.print_semicolon_and_newline(stmt.has_semicolon, IndentationMove::Stay);
+                self.print_semicolon_and_newline(has_semicolon, IndentationMove::Stay);
             }
             Statement::Break => {
                 self.print("break", false);
-                self.print_semicolon_and_newline(stmt.has_semicolon, IndentationMove::Stay);
+                self.print_semicolon_and_newline(has_semicolon, IndentationMove::Stay);
             }
 
             Statement::Exit => {
                 self.print("exit", false);
-                self.print_semicolon_and_newline(stmt.has_semicolon, IndentationMove::Stay);
+                self.print_semicolon_and_newline(has_semicolon, IndentationMove::Stay);
             }
             Statement::Switch {
                 comments_after_control_word,
@@ -586,9 +671,7 @@ impl<'a> Printer<'a> {
                         self.print_newline(IndentationMove::Right);
                     }
                     // @jack do we handle blocks here in a special way?
-                    for this_statement in &case.statements {
-                        self.print_statement(this_statement);
-                    }
+                    self.print_statements(&case.statements);
 
                     self.backspace_till_newline();
                     self.print_indentation_raw(saved_indentation);
@@ -598,14 +681,21 @@ impl<'a> Printer<'a> {
                 self.print_newline(IndentationMove::Left);
 
                 self.print(RBRACE, false);
-                self.print_semicolon(stmt.has_semicolon);
+                self.print_semicolon(has_semicolon);
             }
             Statement::Comment { comment } => self.print_token(comment, true),
             Statement::MultilineComment { multiline_comment } => self.print_token(multiline_comment, true),
-            Statement::RegionBegin(comment) | Statement::RegionEnd(comment) | Statement::Macro(comment) => {
+            Statement::RegionBegin(comment) | Statement::RegionEnd(comment) => {
                 self.print_token(comment, false);
                 self.backspace();
             }
+            Statement::Macro { token, definition } => match definition {
+                Some(definition) => self.print_macro(token, definition),
+                None => {
+                    self.print_token(token, false);
+                    self.backspace();
+                }
+            },
             Statement::Define {
                 comments_after_control_word,
                 script_name,
@@ -621,9 +711,7 @@ impl<'a> Printer<'a> {
                 self.backspace();
                 self.print_newline(IndentationMove::Stay);
 
-                for this_stmt in body {
-                    self.print_statement(this_stmt);
-                }
+                self.print_statements(body);
             }
         }
 
@@ -639,7 +727,7 @@ impl<'a> Printer<'a> {
 
         let okay_to_not_have_semicolon = self.do_not_need_semicolon.pop().is_some();
 
-        if stmt.has_semicolon == false && okay_to_not_have_semicolon == false {
+        if has_semicolon == false && okay_to_not_have_semicolon == false {
             match stmt.statement {
                 Statement::Comment { .. }
                 | Statement::MultilineComment { .. }
@@ -659,8 +747,11 @@ impl<'a> Printer<'a> {
                                 newlines = usize::max(newlines, 1);
                             }
 
+                            // a comment after the semicolon we dropped stays on its line.
+                            _ if stmt.has_semicolon && comment_follows => self.ensure_space(),
+
                             _ => {
-                                self.print_semicolon(true);
+                                self.print_semicolon(self.adds_semicolons(false));
                                 newlines = usize::max(newlines, 1);
                             }
                         }
@@ -668,12 +759,100 @@ impl<'a> Printer<'a> {
                         for _ in 0..newlines {
                             self.print_newline(IndentationMove::Stay);
                         }
+
+                        // the newline after a semicolon we dropped has already been printed.
+                        if stmt.has_semicolon && newlines > 0 {
+                            self.do_not_print_single_newline_statement = true;
+                        }
                     }
                 }
             };
         };
     }
 
+    fn print_statements(&mut self, statements: &'a [StmtBox<'a>]) {
+        let next_statement = self.next_statement.take();
+        for (i, this_statement) in statements.iter().enumerate() {
+            self.next_statement = Printer::following_statement(&statements[i + 1..]);
+            self.comment_follows = Printer::comment_follows(&statements[i + 1..]);
+            self.print_statement(this_statement);
+        }
+        self.next_statement = next_statement;
+    }
+
+    fn print_macro(&mut self, token: &'a Token<'a>, definition: &'a MacroDefinition<'a>) {
+        self.print("#macro", true);
+        if let Some(configuration) = definition.configuration {
+            self.print(configuration, false);
+            self.print(":", false);
+        }
+        self.print(definition.name, true);
+
+        // continuation lines line up with the start of the body.
+        let alignment = self.current_line_as_whitespace();
+
+        match &definition.body {
+            MacroBody::Expression(expression) => {
+                let start = self.output.len();
+                let indentation = std::mem::replace(&mut self.indentation, 0);
+                self.print_expr(expression);
+                self.indentation = indentation;
+                self.backspace();
+
+                self.continue_macro_lines(start, token.line_number, &alignment);
+            }
+            MacroBody::Lines(lines) => {
+                let mut iter = lines.iter().peekable();
+                while let Some(line) = iter.next() {
+                    if line.is_empty() == false {
+                        self.print(line, iter.peek().is_some());
+                    }
+
+                    if iter.peek().is_some() {
+                        self.print("\\", false);
+                        self.print(NEWLINE, false);
+                        for whitespace in &alignment {
+                            self.print(whitespace, false);
+                        }
+                    }
+                }
+                self.backspace();
+            }
+        }
+    }
+
+    // Breaks the body printed since `start` where the source continued it onto another
+    // line, and wherever the printer broke it, ending each line but the last with a `\`.
+    fn continue_macro_lines(&mut self, start: usize, first_line: u32, alignment: &[&'static str]) {
+        let output = self.output.split_off(start);
+        let origins = self.origins.split_off(start);
+
+        let mut line = first_line;
+        for (entry, origin) in output.into_iter().zip(origins) {
+            let continues = match origin {
+                Some(token) if token.line_number > line => {
+                    line = token.line_number;
+                    true
+                }
+                _ => false,
+            };
+
+            if continues || entry == NEWLINE {
+                self.backspace();
+                self.ensure_space();
+                self.print("\\", false);
+                self.print(NEWLINE, false);
+                for whitespace in alignment {
+                    self.print(whitespace, false);
+                }
+            }
+
+            if entry != NEWLINE {
+                self.print_from(origin, entry, false);
+            }
+        }
+    }
+
     fn print_expr(&mut self, expr: &'a ExprBox<'a>) {
         match &expr.expr {
             Expr::Call {
@@ -681,6 +860,8 @@ impl<'a> Printer<'a> {
                 comments_and_newlines_after_lparen,
                 arguments,
             } => {
+                let is_signature = self.in_a_function_signature.pop().is_some();
+
                 // For variable functions
                 if let Expr::UnidentifiedAsLiteral { literal_token } = procedure_name.expr {
                     if literal_token.token_type == TokenType::Function {
@@ -709,7 +890,9 @@ impl<'a> Printer<'a> {
                     }
                 }
 
-                self.print_delimited_lines(arguments, COMMA, false, false);
+                // parameter lists only ever lose a trailing comma.
+                let trailing_comma = self.trailing_comma(arguments.has_end_delimiter, did_move, is_signature == false);
+                self.print_delimited_lines(arguments, COMMA, trailing_comma, false, false);
                 self.backspace_whitespace();
 
                 if did_move {
@@ -734,6 +917,9 @@ impl<'a> Printer<'a> {
                     comments_after_control_word,
                     CommentAndNewlinesInstruction::new(IndentationMove::Stay, LeadingNewlines::One),
                 );
+                if let Expr::Call { .. } = call.expr {
+                    self.in_a_function_signature.push(());
+                }
                 self.print_expr(call);
                 if !*is_constructor {
                     self.backspace_whitespace();
@@ -765,22 +951,45 @@ impl<'a> Printer<'a> {
                 self.backspace_whitespace();
             }
 
-            Expr::Binary {
-                left,
-                operator,
-                comments_and_newlines_between_op_and_r,
-                right,
-            } => {
-                self.print_expr(left);
-                self.ensure_space();
-                self.print_token(operator, true);
-                self.allow_user_indentation();
-                self.print_comments_and_newlines(
-                    comments_and_newlines_between_op_and_r,
-                    CommentAndNewlinesInstruction::new_respect_users(IndentationMove::Stay, LeadingNewlines::All),
-                );
-                self.print_expr(right);
-                self.rewind_user_indentation();
+            Expr::Binary { .. } => {
+                // a chain like `a + b + c` is a tree as deep as the chain is long, with the
+                // first link at the bottom, so we walk down it in a loop.
+                let mut links = Vec::new();
+                let mut first = expr;
+                while let Expr::Binary { left, .. } = &first.expr {
+                    links.push(first);
+                    first = left;
+                }
+                self.print_expr(first);
+
+                let mut iter = links.into_iter().rev().peekable();
+                while let Some(link) = iter.next() {
+                    if let Expr::Binary {
+                        operator,
+                        comments_and_newlines_between_op_and_r,
+                        right,
+                        ..
+                    } = &link.expr
+                    {
+                        self.ensure_space();
+                        self.print_token(operator, true);
+                        self.allow_user_indentation();
+                        self.print_comments_and_newlines(
+                            comments_and_newlines_between_op_and_r,
+                            CommentAndNewlinesInstruction::new_respect_users(
+                                IndentationMove::Stay,
+                                LeadingNewlines::All,
+                            ),
+                        );
+                        self.print_expr(right);
+                        self.rewind_user_indentation();
+                    }
+
+                    // the last link is `expr`, whose comments are printed below.
+                    if iter.peek().is_some() {
+                        self.print_trailing_comments(link);
+                    }
+                }
             }
 
             Expr::Grouping {
@@ -834,7 +1043,10 @@ impl<'a> Printer<'a> {
                     CommentAndNewlinesInstruction::new_respect_users(IndentationMove::Right, LeadingNewlines::One),
                 );
 
-                self.print_delimited_lines(arguments, COMMA, false, false);
+                let trailing_comma = self.trailing_comma(arguments.has_end_delimiter, did_move, true);
+                self.print_delimited_lines(arguments, COMMA, trailing_comma, false, false);
+                self.backspace_whitespace();
+
                 if did_move {
                     self.print_newline(IndentationMove::Left);
                 }
@@ -1047,6 +1259,10 @@ impl<'a> Printer<'a> {
             }
         }
 
+        self.print_trailing_comments(expr);
+    }
+
+    fn print_trailing_comments(&mut self, expr: &'a ExprBox<'a>) {
         self.print_comments_and_newlines(
             &expr.trailing_comments,
             CommentAndNewlinesInstruction {
@@ -1060,16 +1276,27 @@ impl<'a> Printer<'a> {
     }
 
     fn print_token(&mut self, token: &'a Token<'a>, space_after: bool) {
-        self.print(Printer::get_token_name(&token.token_type), space_after);
+        self.print_from(Some(token), Printer::get_token_name(&token.token_type), space_after);
     }
 
     fn print(&mut self, this_string: &'a str, space_after: bool) {
+        self.print_from(None, this_string, space_after);
+    }
+
+    fn print_from(&mut self, origin: Option<&'a Token<'a>>, this_string: &'a str, space_after: bool) {
         self.output.push(this_string);
+        self.origins.push(origin);
         if space_after {
             self.output.push(SPACE);
+            self.origins.push(None);
         }
     }
 
+    fn remove_entry(&mut self, pos: usize) {
+        self.output.remove(pos);
+        self.origins.remove(pos);
+    }
+
     fn on_whitespace_line(&self) -> bool {
         let mut pos = self.output.len();
         if pos == 0 {
@@ -1091,6 +1318,23 @@ impl<'a> Printer<'a> {
         false
     }
 
+    // turns everything on the current line into whitespace of the same width,
+    // keeping tabs as tabs so alignment survives any tab width.
+    fn current_line_as_whitespace(&self) -> Vec<&'static str> {
+        let mut alignment = Vec::new();
+
+        for this_one in self.output.iter().rev() {
+            match *this_one {
+                NEWLINE => break,
+                TAB => alignment.push(TAB),
+                _ => alignment.extend(this_one.chars().map(|_| SPACE)),
+            }
+        }
+
+        alignment.reverse();
+        alignment
+    }
+
     fn prev_line_was_whitespace(&self) -> bool {
         let mut pos = self.output.len();
         if pos < 2 {
@@ -1132,7 +1376,7 @@ impl<'a> Printer<'a> {
             match self.output[pos] {
                 NEWLINE => break,
                 _ => {
-                    self.output.remove(pos);
+                    self.remove_entry(pos);
                     pos -= 1;
                 }
             };
@@ -1152,12 +1396,12 @@ impl<'a> Printer<'a> {
         while pos != 0 {
             match self.output[pos] {
                 NEWLINE => {
-                    self.output.remove(pos);
+                    self.remove_entry(pos);
                     pos -= 1;
                     newline_number += 1;
                 }
                 TAB | SPACE => {
-                    self.output.remove(pos);
+                    self.remove_entry(pos);
                     pos -= 1;
                 }
                 _ => break,
@@ -1169,7 +1413,7 @@ impl<'a> Printer<'a> {
     fn backspace(&mut self) {
         let pos = self.output.len();
         if pos != 0 && self.on_whitespace_line() == false && self.output[pos - 1] == SPACE {
-            self.output.remove(pos - 1);
+            self.remove_entry(pos - 1);
         }
     }
 
@@ -1237,7 +1481,7 @@ impl<'a> Printer<'a> {
             IndentationMove::Right => self.indentation += 1,
             IndentationMove::Stay => {}
             IndentationMove::Left => {
-                self.indentation -= 1;
+                self.indentation = self.indentation.saturating_sub(1);
             }
         }
     }
@@ -1246,7 +1490,7 @@ impl<'a> Printer<'a> {
         match indentation_move {
             IndentationMove::Right => self.indentation + 1,
             IndentationMove::Stay => self.indentation,
-            IndentationMove::Left => self.indentation - 1,
+            IndentationMove::Left => self.indentation.saturating_sub(1),
         }
     }
 
@@ -1467,6 +1711,7 @@ impl<'a> Printer<'a> {
         &mut self,
         delimited_lines: &'a DelimitedLines<'a, ExprBox<'a>>,
         delimiter: &'static str,
+        trailing_delimiter: bool,
         force_newline_between: bool,
         force_newline_at_end: bool,
     ) {
@@ -1479,7 +1724,7 @@ impl<'a> Printer<'a> {
                 self.print(delimiter, true);
                 false
             } else {
-                if delimited_lines.has_end_delimiter {
+                if trailing_delimiter {
                     self.print(delimiter, true);
                 }
                 true
@@ -1508,6 +1753,17 @@ impl<'a> Printer<'a> {
         }
     }
 
+    /// Whether the last item of a list gets a comma. A list is multiline when its closing
+    /// bracket goes on a line of its own, or a `var` list when it runs over more than one.
+    fn trailing_comma(&self, has_end_delimiter: bool, multiline: bool, can_add: bool) -> bool {
+        match self.lang_config.trailing_commas {
+            TrailingCommas::Always => has_end_delimiter || (multiline && can_add),
+            TrailingCommas::Never => false,
+            TrailingCommas::Multiline => multiline && (has_end_delimiter || can_add),
+            TrailingCommas::Preserve => has_end_delimiter,
+        }
+    }
+
     fn print_semicolon(&mut self, do_it: bool) {
         if do_it {
             self.backspace();
@@ -1520,18 +1776,125 @@ impl<'a> Printer<'a> {
             self.print_semicolon(true);
             false
         } else {
-            self.print_semicolon(true);
+            self.print_semicolon(self.adds_semicolons(false));
             self.print_newline(indentation_move);
             true
         }
     }
 
+    /// Whether a statement which came in with (or without) a semicolon goes out with one.
+    fn adds_semicolons(&self, has_semicolon: bool) -> bool {
+        has_semicolon || self.lang_config.semicolons == Semicolons::Always || self.in_a_for_loop.is_empty() == false
+    }
+
+    fn keeps_semicolon(&self, stmt: &StatementWrapper<'a>) -> bool {
+        match self.lang_config.semicolons {
+            Semicolons::Always | Semicolons::Preserve => stmt.has_semicolon,
+            Semicolons::Never => {
+                stmt.has_semicolon && (self.in_a_for_loop.is_empty() == false || self.semicolon_is_required(stmt))
+            }
+        }
+    }
+
+    // Without its semicolon, a statement runs straight into the next one if the next
+    // one could carry on its expression, eg. `a = b` followed by `(c)()` or `-d`.
+    fn semicolon_is_required(&self, stmt: &StatementWrapper<'a>) -> bool {
+        let next_statement = match self.next_statement {
+            Some(next_statement) => next_statement,
+            None => return false,
+        };
+
+        match &stmt.statement {
+            // a bare return would take the next statement as its value.
+            Statement::Return { expression: None } => true,
+            Statement::ExpressionStatement { .. }
+            | Statement::VariableDeclList { .. }
+            | Statement::Return { .. }
+            | Statement::DoUntil { .. } => match &next_statement.statement {
+                Statement::ExpressionStatement { expression } => Printer::continues_expression(expression),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    // Follows the expression down to its first token in a loop, as a chain can run deep.
+    fn continues_expression(mut expr: &ExprBoxInterior<'a>) -> bool {
+        loop {
+            expr = match &expr.expr {
+                Expr::Grouping { .. } | Expr::ArrayLiteral { .. } | Expr::NumberStartDot { .. } => return true,
+                Expr::Unary { operator, .. } => {
+                    return matches!(
+                        operator.token_type,
+                        TokenType::Minus | TokenType::Plus | TokenType::Incrementer | TokenType::Decrementer
+                    )
+                }
+                Expr::Binary { left, .. } | Expr::Assign { left, .. } => left,
+                Expr::Call { procedure_name, .. } => procedure_name,
+                Expr::Postfix { expr, .. } => expr,
+                Expr::DotAccess { object_name, .. } => object_name,
+                // a leading `[` is parsed as an access on whatever came before it.
+                Expr::DataStructureAccess { ds_name, .. } => match ds_name.expr {
+                    Expr::Newline | Expr::Comment { .. } | Expr::MultilineComment { .. } => return true,
+                    _ => ds_name,
+                },
+                Expr::Ternary { conditional, .. } => conditional,
+                _ => return false,
+            };
+        }
+    }
+
+    // Without a semicolon, the newline after an expression statement is kept with it, where
+    // with one it would be a statement of its own.
+    fn ends_line(stmt: &StatementWrapper<'a>) -> bool {
+        match &stmt.statement {
+            Statement::ExpressionStatement { expression } => match &expression.trailing_comments {
+                Some(tokens) => tokens
+                    .iter()
+                    .any(|token| matches!(token.token_type, TokenType::Newline(_))),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    // A comment statement straight after another is on the same line as its end.
+    fn comment_follows(statements: &'a [StmtBox<'a>]) -> bool {
+        matches!(
+            statements.first().map(|stmt| &stmt.statement),
+            Some(Statement::Comment { .. }) | Some(Statement::MultilineComment { .. })
+        )
+    }
+
+    // Comments, blank lines and directives don't stop one statement running into the next.
+    fn following_statement(statements: &'a [StmtBox<'a>]) -> Option<&'a StatementWrapper<'a>> {
+        statements
+            .iter()
+            .find(|stmt| match &stmt.statement {
+                Statement::Comment { .. }
+                | Statement::MultilineComment { .. }
+                | Statement::RegionBegin(_)
+                | Statement::RegionEnd(_)
+                | Statement::Macro { .. } => false,
+                Statement::ExpressionStatement { expression } => {
+                    matches!(
+                        expression.expr,
+                        Expr::Newline | Expr::Comment { .. } | Expr::MultilineComment { .. }
+                    ) == false
+                }
+                _ => true,
+            })
+            .map(|stmt| &**stmt)
+    }
+
     fn allow_user_indentation(&mut self) {
         self.user_indentation_instructions.push(self.indentation);
     }
 
     fn rewind_user_indentation(&mut self) {
-        self.indentation = self.user_indentation_instructions.pop().unwrap();
+        if let Some(indentation) = self.user_indentation_instructions.pop() {
+            self.indentation = indentation;
+        }
     }
 }
 
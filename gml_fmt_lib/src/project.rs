@@ -0,0 +1,326 @@
+use super::lang_config::edit_distance;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::{error::Error, ffi::OsStr, fmt, fs, io};
+
+// The names GameMaker gives event files, by event type.
+const EVENT_NAMES: [&str; 15] = [
+    "Create",
+    "Destroy",
+    "Alarm",
+    "Step",
+    "Collision",
+    "Keyboard",
+    "Mouse",
+    "Other",
+    "Draw",
+    "KeyPress",
+    "KeyRelease",
+    "Trigger",
+    "CleanUp",
+    "Gesture",
+    "PreCreate",
+];
+const COLLISION: u64 = 4;
+
+/// The scripts and objects of a GameMaker project, as its `.yyp` lists them. Anything
+/// sitting in the project folder which the `.yyp` doesn't know about is left out.
+pub struct Project {
+    pub folder: PathBuf,
+    pub resources: Vec<Resource>,
+    /// Resources and files the project lists, but which we couldn't find or read.
+    pub warnings: Vec<String>,
+}
+
+pub struct Resource {
+    pub name: String,
+    pub kind: ResourceKind,
+    /// The files holding the resource's code: a script's body, or each of an object's events.
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ResourceKind {
+    Script,
+    Object,
+}
+
+impl Project {
+    /// The `.yyp` in `directory`, if there is one.
+    pub fn find(directory: &Path) -> Option<PathBuf> {
+        let yyp = OsStr::new("yyp");
+
+        let mut found: Vec<PathBuf> = fs::read_dir(directory)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension() == Some(yyp) && path.is_file())
+            .collect();
+        found.sort();
+        found.into_iter().next()
+    }
+
+    /// Whether `directory` holds a `.yyp`, which makes it the top of the walks looking for
+    /// settings.
+    pub(crate) fn is_project_folder(directory: &Path) -> bool {
+        Project::find(directory).is_some()
+    }
+
+    /// Reads a GMS 2.2 or 2.3 `.yyp`, and the `.yy` of each script and object in it.
+    pub fn read(yyp_path: &Path) -> Result<Project, ProjectError> {
+        let yyp = read_json(yyp_path)?;
+        let folder = yyp_path.parent().unwrap_or_else(|| Path::new("")).to_owned();
+
+        let mut project = Project {
+            folder,
+            resources: Vec::new(),
+            warnings: Vec::new(),
+        };
+
+        let entries = yyp["resources"].as_array().map(Vec::as_slice).unwrap_or_default();
+        for entry in entries {
+            // 2.2 keeps the path under `Value`, and 2.3 under `id`.
+            let resource_path = entry
+                .pointer("/Value/resourcePath")
+                .or_else(|| entry.pointer("/id/path"))
+                .and_then(Value::as_str);
+            let resource_path = match resource_path {
+                Some(resource_path) => resource_path.replace('\\', "/"),
+                None => continue,
+            };
+
+            // We only need to open the `.yy` files of scripts and objects.
+            let might_have_code = match entry.pointer("/Value/resourceType").and_then(Value::as_str) {
+                Some(resource_type) => resource_type == "GMScript" || resource_type == "GMObject",
+                None => resource_path.starts_with("scripts/") || resource_path.starts_with("objects/"),
+            };
+
+            if might_have_code {
+                if let Some(resource) = project.read_resource(&resource_path) {
+                    project.resources.push(resource);
+                }
+            }
+        }
+
+        project.resources.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(project)
+    }
+
+    /// Every file of the resources called `names`, or of all of them if `names` is empty.
+    pub fn files(&self, names: &[String]) -> Result<Vec<PathBuf>, ProjectError> {
+        if names.is_empty() {
+            return Ok(self
+                .resources
+                .iter()
+                .flat_map(|resource| resource.files.clone())
+                .collect());
+        }
+
+        let mut files = Vec::new();
+        for name in names {
+            match self.resources.iter().find(|resource| &resource.name == name) {
+                Some(resource) => files.extend(resource.files.iter().cloned()),
+                None => {
+                    let suggestion = self
+                        .resources
+                        .iter()
+                        .map(|resource| (edit_distance(name, &resource.name), &resource.name))
+                        .filter(|(distance, _)| *distance <= 3)
+                        .min_by_key(|(distance, _)| *distance)
+                        .map(|(_, name)| name.clone());
+
+                    return Err(ProjectError {
+                        path: self.folder.clone(),
+                        kind: ProjectErrorKind::UnknownResource {
+                            name: name.clone(),
+                            suggestion,
+                        },
+                    });
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn read_resource(&mut self, resource_path: &str) -> Option<Resource> {
+        let yy_path = self.folder.join(resource_path);
+        let yy = match read_json(&yy_path) {
+            Ok(yy) => yy,
+            Err(e) => {
+                self.warnings.push(e.to_string());
+                return None;
+            }
+        };
+
+        let kind = match yy["resourceType"].as_str().or_else(|| yy["modelName"].as_str()) {
+            Some("GMScript") => ResourceKind::Script,
+            Some("GMObject") => ResourceKind::Object,
+            _ => return None,
+        };
+        let name = yy["name"].as_str()?.to_owned();
+        let resource_folder = yy_path.parent()?;
+
+        let file_names = match kind {
+            ResourceKind::Script => vec![format!("{}.gml", name)],
+            ResourceKind::Object => yy["eventList"]
+                .as_array()
+                .map(Vec::as_slice)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(Project::event_file_name)
+                .collect(),
+        };
+
+        let mut files = Vec::new();
+        for file_name in file_names {
+            let path = resource_folder.join(file_name);
+            if path.is_file() {
+                files.push(path);
+            } else {
+                self.warnings
+                    .push(format!("{} lists {:?}, which does not exist.", name, path));
+            }
+        }
+
+        Some(Resource { name, kind, files })
+    }
+
+    // Events made with drag and drop have no code for us to format.
+    fn event_file_name(event: &Value) -> Option<String> {
+        let is_dnd = event["IsDnD"].as_bool().or_else(|| event["isDnD"].as_bool());
+        if is_dnd == Some(true) {
+            return None;
+        }
+
+        let event_type = event["eventtype"].as_u64().or_else(|| event["eventType"].as_u64())?;
+        let event_name = EVENT_NAMES.get(event_type as usize)?;
+
+        // 2.2 names collision events after the other object's id, and 2.3 after its name.
+        let number = if event_type == COLLISION {
+            match &event["collisionObjectId"] {
+                Value::String(id) => id.clone(),
+                other => other["name"].as_str()?.to_owned(),
+            }
+        } else {
+            event["enumb"]
+                .as_u64()
+                .or_else(|| event["eventNum"].as_u64())?
+                .to_string()
+        };
+
+        Some(format!("{}_{}.gml", event_name, number))
+    }
+}
+
+fn read_json(path: &Path) -> Result<Value, ProjectError> {
+    let error = |kind| ProjectError {
+        path: path.to_owned(),
+        kind,
+    };
+
+    let contents = fs::read_to_string(path).map_err(|e| error(ProjectErrorKind::Io(e)))?;
+    serde_json::from_str(&without_trailing_commas(&contents)).map_err(|e| error(ProjectErrorKind::Json(e)))
+}
+
+// GMS 2.3 writes a comma after the last item of every object and array, which JSON doesn't allow.
+fn without_trailing_commas(json: &str) -> String {
+    let mut output = String::with_capacity(json.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut pending_comma = None;
+
+    for c in json.chars() {
+        if in_string {
+            output.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            ',' => {
+                if let Some(whitespace) = pending_comma.replace(String::new()) {
+                    output.push(',');
+                    output.push_str(&whitespace);
+                }
+            }
+            c if c.is_whitespace() && pending_comma.is_some() => {
+                if let Some(whitespace) = &mut pending_comma {
+                    whitespace.push(c);
+                }
+            }
+            '}' | ']' => {
+                if let Some(whitespace) = pending_comma.take() {
+                    output.push_str(&whitespace);
+                }
+                output.push(c);
+            }
+            c => {
+                if let Some(whitespace) = pending_comma.take() {
+                    output.push(',');
+                    output.push_str(&whitespace);
+                }
+                in_string = c == '"';
+                output.push(c);
+            }
+        }
+    }
+
+    if let Some(whitespace) = pending_comma {
+        output.push(',');
+        output.push_str(&whitespace);
+    }
+
+    output
+}
+
+/// A project we couldn't read, or a resource it doesn't have.
+#[derive(Debug)]
+pub struct ProjectError {
+    pub path: PathBuf,
+    pub kind: ProjectErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ProjectErrorKind {
+    Io(io::Error),
+    Json(serde_json::Error),
+    NoProject,
+    UnknownResource { name: String, suggestion: Option<String> },
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ProjectErrorKind::Io(e) => write!(f, "{}: {}", self.path.display(), e),
+            ProjectErrorKind::Json(e) => write!(f, "{}: {}", self.path.display(), e),
+            ProjectErrorKind::NoProject => write!(f, "{}: no `.yyp` project file here", self.path.display()),
+            ProjectErrorKind::UnknownResource {
+                name,
+                suggestion: Some(suggestion),
+            } => write!(
+                f,
+                "the project has no script or object called `{}`, did you mean `{}`?",
+                name, suggestion
+            ),
+            ProjectErrorKind::UnknownResource { name, suggestion: None } => {
+                write!(f, "the project has no script or object called `{}`", name)
+            }
+        }
+    }
+}
+
+impl Error for ProjectError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            ProjectErrorKind::Io(e) => Some(e),
+            ProjectErrorKind::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
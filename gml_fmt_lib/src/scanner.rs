@@ -41,20 +41,40 @@ pub struct Scanner<'a> {
     line_number: u32,
     column_number: u32,
     iter: Peekable<CharIndices<'a>>,
+    token_start: usize,
+    offset: usize,
+    join_lines: bool,
 }
 
 impl<'a> Scanner<'a> {
     pub fn new(input: &'a str) -> Scanner<'a> {
+        Scanner::starting_at(input, 0, 0, 0)
+    }
+
+    /// Scans `input` as if it sat at `offset` in a larger source, on that line and column,
+    /// such as the body of a macro.
+    pub fn starting_at(input: &'a str, offset: usize, line_number: u32, column_number: u32) -> Scanner<'a> {
         Scanner {
             input,
-            line_number: 0,
-            column_number: 0,
+            line_number,
+            column_number,
             iter: input.char_indices().peekable(),
+            token_start: 0,
+            offset,
+            join_lines: false,
         }
     }
 
+    /// Skips a `\` at the end of a line along with the line break, so the lines of a
+    /// macro body scan as one.
+    pub fn joining_lines(mut self) -> Scanner<'a> {
+        self.join_lines = true;
+        self
+    }
+
     pub fn lex_input(&mut self) -> Option<Token<'a>> {
         while let Some((i, c)) = self.iter.next() {
+            self.token_start = i;
             let found_token = match c {
                 '(' => self.add_simple_token(TokenType::LeftParen),
                 ')' => self.add_simple_token(TokenType::RightParen),
@@ -113,7 +133,16 @@ impl<'a> Scanner<'a> {
                 }
                 ']' => self.add_simple_token(TokenType::RightBracket),
                 '?' => self.add_simple_token(TokenType::Hook),
-                '\\' => self.add_simple_token(TokenType::Backslash),
+                '\\' => {
+                    let rest = &self.input[i + 1..];
+                    if self.join_lines && (rest.starts_with('\n') || rest.starts_with("\r\n")) {
+                        self.peek_and_check_consume('\r');
+                        self.iter.next();
+                        self.next_line();
+                        continue;
+                    }
+                    self.add_simple_token(TokenType::Backslash)
+                }
                 '!' => {
                     if self.peek_and_check_consume('=') {
                         self.add_multiple_token(TokenType::BangEqual, 2)
@@ -268,6 +297,7 @@ impl<'a> Scanner<'a> {
 
                                     '\\' => {
                                         self.iter.next();
+                                        self.peek_and_check_consume('\r');
                                         if self.peek_and_check_consume('\n') {
                                             last_column_break = self.next_char_boundary();
                                             is_multiline = true;
@@ -276,11 +306,11 @@ impl<'a> Scanner<'a> {
                                     }
 
                                     _ => {
-                                        self.iter.next().unwrap();
+                                        self.iter.next();
                                     }
                                 }
                             }
-                            current = self.next_char_boundary();
+                            current = self.end_without_carriage_return();
                             Some(TokenType::Macro(&self.input[start..current]))
                         }
                         "#region" => {
@@ -288,22 +318,24 @@ impl<'a> Scanner<'a> {
                                 match peek_char {
                                     '\n' => break,
                                     _ => {
-                                        self.iter.next().unwrap();
+                                        self.iter.next();
                                     }
                                 }
                             }
-                            Some(TokenType::RegionBegin(&self.input[start..self.next_char_boundary()]))
+                            let end = self.end_without_carriage_return();
+                            Some(TokenType::RegionBegin(&self.input[start..end]))
                         }
                         "#endregion" => {
                             while let Some((_, peek_char)) = self.iter.peek() {
                                 match peek_char {
                                     '\n' => break,
                                     _ => {
-                                        self.iter.next().unwrap();
+                                        self.iter.next();
                                     }
                                 }
                             }
-                            Some(TokenType::RegionEnd(&self.input[start..self.next_char_boundary()]))
+                            let end = self.end_without_carriage_return();
+                            Some(TokenType::RegionEnd(&self.input[start..end]))
                         }
                         "#define" => Some(TokenType::Define),
                         _ => None,
@@ -314,7 +346,8 @@ impl<'a> Scanner<'a> {
                         Some(macro_directive) => {
                             if is_multiline {
                                 self.column_number += (current - last_column_break) as u32;
-                                Token::new(TokenType::Macro(&self.input[start..current]), start_line, start_column)
+                                let end = self.end_without_carriage_return();
+                                Token::new(TokenType::Macro(&self.input[start..end]), start_line, start_column)
                             } else {
                                 self.add_multiple_token(macro_directive, (current - start) as u32)
                             }
@@ -345,8 +378,9 @@ impl<'a> Scanner<'a> {
                     if let Some((_, this_char)) = self.iter.peek() {
                         match this_char {
                             '\'' | '\"' => {
-                                let (_, this_char) = self.iter.next().unwrap();
-                                let (current, last_column_break) = self.scan_multiline_string(start, this_char);
+                                let quote = *this_char;
+                                self.iter.next();
+                                let (current, last_column_break) = self.scan_multiline_string(start, quote);
 
                                 self.column_number += (current - last_column_break) as u32;
                                 Token::new(TokenType::String(&self.input[start..current]), start_line, start_column)
@@ -553,7 +587,7 @@ impl<'a> Scanner<'a> {
                             }
                             self.iter.next();
                         }
-                        let current = self.next_char_boundary();
+                        let current = self.end_without_carriage_return();
 
                         self.add_multiple_token(
                             TokenType::Comment(&self.input[start..current]),
@@ -655,12 +689,17 @@ impl<'a> Scanner<'a> {
                     }
                     let ret = self.add_multiple_token(TokenType::Newline(tally / 4), tally as u32);
                     self.next_line();
+                    // The indentation is on the next line, so what follows it starts after it.
+                    self.column_number = (self.next_char_boundary() - i - 1) as u32;
                     ret
                 }
 
                 // Whitespace we don't care about
                 '\r' => continue,
 
+                // The byte order mark, which is put back after printing. Anywhere else it's text.
+                '\u{feff}' if i == 0 => continue,
+
                 _ => self.return_unidentified_input(i),
             };
 
@@ -670,6 +709,16 @@ impl<'a> Scanner<'a> {
         None
     }
 
+    // Tokens which run to the end of the line stop before the `\r` of a `\r\n`.
+    fn end_without_carriage_return(&mut self) -> usize {
+        let end = self.next_char_boundary();
+        if self.input[..end].ends_with('\r') {
+            end - 1
+        } else {
+            end
+        }
+    }
+
     fn return_unidentified_input(&mut self, start: usize) -> Token<'a> {
         let end_byte = self.next_char_boundary();
         self.add_multiple_token(
@@ -719,7 +768,7 @@ impl<'a> Scanner<'a> {
 
     fn next_char_boundary(&mut self) -> usize {
         match self.iter.peek() {
-            Some(_) => self.iter.peek().unwrap().0,
+            Some(&(i, _)) => i,
             None => self.input.len(),
         }
     }
@@ -729,7 +778,14 @@ impl<'a> Iterator for Scanner<'a> {
     type Item = Token<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.lex_input()
+        let token = self.lex_input()?;
+
+        // Tokens which run to the end of the line stop before the `\r` of a `\r\n`.
+        let mut end = self.next_char_boundary();
+        if self.input[self.token_start..end].ends_with('\r') {
+            end -= 1;
+        }
+        Some(token.with_span(self.offset + self.token_start, self.offset + end))
     }
 }
 
@@ -738,6 +794,61 @@ mod scanner_test {
     use super::Scanner;
     use super::*;
 
+    // Most tests only check where tokens are by line and column.
+    fn without_spans(input: &str) -> Vec<Token<'_>> {
+        Scanner::new(input)
+            .map(|token| Token {
+                span: Span::default(),
+                ..token
+            })
+            .collect()
+    }
+
+    #[test]
+    fn spans() {
+        let input = "var a = \"é\"; // hi\r\n#macro B 1\r\n";
+        let spans: Vec<(TokenType<'_>, usize, usize)> = Scanner::new(input)
+            .map(|token| (token.token_type, token.span.start, token.span.end))
+            .collect();
+
+        assert_eq!(
+            spans,
+            vec![
+                (TokenType::Var, 0, 3),
+                (TokenType::Identifier("a"), 4, 5),
+                (TokenType::Equal, 6, 7),
+                (TokenType::String("\"é\""), 8, 12),
+                (TokenType::Semicolon, 12, 13),
+                (TokenType::Comment("// hi"), 14, 19),
+                (TokenType::Newline(0), 20, 21),
+                (TokenType::Macro("#macro B 1"), 21, 31),
+                (TokenType::Newline(0), 32, 33),
+            ]
+        );
+        for (_, start, end) in spans {
+            assert!(input.is_char_boundary(start) && input.is_char_boundary(end));
+        }
+    }
+
+    #[test]
+    fn joining_lines() {
+        let tokens: Vec<(TokenType<'_>, u32, u32)> = Scanner::new("a + \\\r\n  b \\ c")
+            .joining_lines()
+            .map(|token| (token.token_type, token.line_number, token.column_number))
+            .collect();
+
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenType::Identifier("a"), 0, 0),
+                (TokenType::Plus, 0, 2),
+                (TokenType::Identifier("b"), 1, 2),
+                (TokenType::Backslash, 1, 4),
+                (TokenType::Identifier("c"), 1, 6),
+            ]
+        );
+    }
+
     #[test]
     fn lex_symbols<'a>() {
         let input_string = "(){}[] // grouping stuff
@@ -747,8 +858,7 @@ mod scanner_test {
 && || ^^ // logical operators
 += -= *= /= ^= |= &= %= // set operators";
 
-        let scanner = Scanner::new(input_string);
-        let vec: Vec<Token<'a>> = scanner.collect();
+        let vec: Vec<Token<'a>> = without_spans(input_string);
         assert_eq!(
             vec,
             vec![
@@ -820,8 +930,7 @@ mod scanner_test {
 \"This is another good string!\"
 @\"This is a
 multi-linestring. The demon's plaything!\"";
-        let scanner = Scanner::new(input_string);
-        let vec: Vec<Token<'a>> = scanner.collect();
+        let vec: Vec<Token<'a>> = without_spans(input_string);
         assert_eq!(
             &vec,
             &vec![
@@ -853,8 +962,7 @@ multi-linestring. The demon's plaything!\"";
 0
 .3";
 
-        let scanner = Scanner::new(input_string);
-        let vec: Vec<Token<'a>> = scanner.collect();
+        let vec: Vec<Token<'a>> = without_spans(input_string);
         assert_eq!(
             &vec,
             &vec![
@@ -889,8 +997,7 @@ $0A1B2C3D4E5F6
 $ABCDEF
 $";
 
-        let scanner = Scanner::new(input_string);
-        let vec: Vec<Token<'a>> = scanner.collect();
+        let vec: Vec<Token<'a>> = without_spans(input_string);
         assert_eq!(
             &vec,
             &vec![
@@ -925,8 +1032,7 @@ _test123
 test_123
 testCase";
 
-        let scanner = Scanner::new(input_string);
-        let vec: Vec<Token<'a>> = scanner.collect();
+        let vec: Vec<Token<'a>> = without_spans(input_string);
         assert_eq!(
             &vec,
             &vec![
@@ -951,8 +1057,7 @@ testCase";
     fn lex_reserved_keywords<'a>() {
         let input_string = "var and or if else return for repeat while do until switch case default div break enum function constructor new";
 
-        let scanner = Scanner::new(input_string);
-        let vec: Vec<Token<'a>> = scanner.collect();
+        let vec: Vec<Token<'a>> = without_spans(input_string);
         assert_eq!(
             &vec,
             &vec![
@@ -984,8 +1089,7 @@ testCase";
     fn lex_alias_words<'a>() {
         let input_string = "and not or mod";
 
-        let scanner = Scanner::new(input_string);
-        let vec: Vec<Token<'a>> = scanner.collect();
+        let vec: Vec<Token<'a>> = without_spans(input_string);
         assert_eq!(
             &vec,
             &vec![
@@ -1001,8 +1105,7 @@ testCase";
     fn lex_indexers<'a>() {
         let input_string = "[ [? [# [| [@ ]";
 
-        let scanner = Scanner::new(input_string);
-        let vec: Vec<Token<'a>> = scanner.collect();
+        let vec: Vec<Token<'a>> = without_spans(input_string);
         assert_eq!(
             &vec,
             &vec![
@@ -1024,8 +1127,7 @@ testCase";
 #macro doing this \\
 is bad";
 
-        let scanner = Scanner::new(input_string);
-        let vec: Vec<Token<'a>> = scanner.collect();
+        let vec: Vec<Token<'a>> = without_spans(input_string);
         assert_eq!(
             &vec,
             &vec![
@@ -1047,8 +1149,7 @@ var x = a; // end comment
 /* multi
 liner comment
 */";
-        let scanner = Scanner::new(input_string);
-        let vec: Vec<Token<'a>> = scanner.collect();
+        let vec: Vec<Token<'a>> = without_spans(input_string);
         assert_eq!(
             &vec,
             &vec![
@@ -0,0 +1,82 @@
+use super::lex_token::{Span, Token};
+use super::line_index::LineCol;
+
+/// One token of the output, and the token in the source it was printed from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    /// Where the token is in the output.
+    pub output: Span,
+    /// Where the token was in the source.
+    pub original: Span,
+    /// The line and column the token was on in the source, as the scanner counted them.
+    pub original_position: LineCol,
+}
+
+impl Mapping {
+    pub(crate) fn new(output: Span, token: &Token<'_>) -> Mapping {
+        Mapping {
+            output,
+            original: token.span,
+            original_position: LineCol {
+                line: token.line_number,
+                column: token.column_number,
+            },
+        }
+    }
+}
+
+/// Maps positions in formatted output back to the source it was formatted from, and the
+/// other way. Only tokens the printer takes from the source are mapped; the keywords,
+/// punctuation and whitespace it writes itself, such as inserted braces, are not, so
+/// positions between mapped tokens keep their distance from the token before them, as
+/// far as the token after them allows.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    // In the order they are in the output, which printing keeps them in the source order of.
+    mappings: Vec<Mapping>,
+    source_len: usize,
+    output_len: usize,
+}
+
+impl SourceMap {
+    pub(crate) fn new(mappings: Vec<Mapping>, source_len: usize, output_len: usize) -> SourceMap {
+        SourceMap {
+            mappings,
+            source_len,
+            output_len,
+        }
+    }
+
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    /// The mapping of the token at `output_offset`, or of the last token before it.
+    pub fn mapping_at(&self, output_offset: usize) -> Option<&Mapping> {
+        let after = self.mappings.partition_point(|m| m.output.start <= output_offset);
+        after.checked_sub(1).map(|i| &self.mappings[i])
+    }
+
+    /// Where `output_offset` was in the source.
+    pub fn original_offset(&self, output_offset: usize) -> usize {
+        self.translate(output_offset, |m| (m.output, m.original), self.source_len)
+    }
+
+    /// Where `original_offset` in the source ended up in the output, like Prettier's
+    /// `cursorOffset`.
+    pub fn output_offset(&self, original_offset: usize) -> usize {
+        self.translate(original_offset, |m| (m.original, m.output), self.output_len)
+    }
+
+    // Offsets inside a token keep their place in it, as far as it is as long as it was.
+    fn translate(&self, offset: usize, spans: fn(&Mapping) -> (Span, Span), to_len: usize) -> usize {
+        let after = self.mappings.partition_point(|m| spans(m).0.start <= offset);
+        let limit = self.mappings.get(after).map_or(to_len, |m| spans(m).1.start);
+
+        match after.checked_sub(1).map(|i| spans(&self.mappings[i])) {
+            Some((from, to)) if offset < from.end => (to.start + offset - from.start).min(to.end),
+            Some((from, to)) => (to.end + offset - from.end).min(limit),
+            None => offset.min(limit),
+        }
+    }
+}
@@ -1,18 +1,24 @@
 use super::expressions::*;
-use super::lex_token::Token;
+use super::lex_token::{Span, Token};
+use serde::Serialize;
 
 pub type StmtBox<'a> = Box<StatementWrapper<'a>>;
-#[derive(Debug)]
+/// Comma separated items, such as arguments or enum members, each with the comments
+/// which follow it.
+#[derive(Debug, Serialize)]
 pub struct DelimitedLines<'a, T> {
     pub lines: Vec<DelimitedLine<'a, T>>,
     pub has_end_delimiter: bool,
 
 }
 
-#[derive(Debug)]
+/// A statement, and whether it was written with a semicolon. Its span runs from its first
+/// token to its last, including the semicolon.
+#[derive(Debug, Serialize)]
 pub struct StatementWrapper<'a> {
     pub statement: Statement<'a>,
     pub has_semicolon: bool,
+    pub span: Span,
 }
 
 impl<'a> StatementWrapper<'a> {
@@ -20,11 +26,12 @@ impl<'a> StatementWrapper<'a> {
         Box::new(StatementWrapper {
             statement,
             has_semicolon,
+            span: Span::default(),
         })
     }
 
     pub fn hold_expr(&self) -> bool {
-        if let Statement::ExpresssionStatement { .. } = &self.statement {
+        if let Statement::ExpressionStatement { .. } = &self.statement {
             true
         } else {
             false
@@ -32,7 +39,9 @@ impl<'a> StatementWrapper<'a> {
     }
 }
 
-#[derive(Debug)]
+/// One statement. Comments between its parts are kept in the `comments_*` fields, in
+/// the order they were written.
+#[derive(Debug, Serialize)]
 pub enum Statement<'a> {
     VariableDeclList {
         starting_var_type: Token<'a>,
@@ -50,6 +59,7 @@ pub enum Statement<'a> {
         condition: ExprBox<'a>,
         then_branch: StmtBox<'a>,
         comments_between: CommentsAndNewlines<'a>,
+        comments_after_else: CommentsAndNewlines<'a>,
         else_branch: Option<StmtBox<'a>>,
     },
     WhileWithRepeat {
@@ -82,7 +92,7 @@ pub enum Statement<'a> {
         comments_after_lbrace: CommentsAndNewlines<'a>,
         cases: Vec<Case<'a>>,
     },
-    ExpresssionStatement {
+    ExpressionStatement {
         expression: ExprBox<'a>,
     },
     Block {
@@ -102,7 +112,10 @@ pub enum Statement<'a> {
     },
     RegionBegin(Token<'a>),
     RegionEnd(Token<'a>),
-    Macro(Token<'a>),
+    Macro {
+        token: Token<'a>,
+        definition: Option<MacroDefinition<'a>>,
+    },
     Define {
         comments_after_control_word: CommentsAndNewlines<'a>,
         script_name: ExprBox<'a>,
@@ -110,7 +123,21 @@ pub enum Statement<'a> {
     },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+pub struct MacroDefinition<'a> {
+    pub configuration: Option<&'a str>,
+    pub name: &'a str,
+    pub body: MacroBody<'a>,
+}
+
+#[derive(Debug, Serialize)]
+pub enum MacroBody<'a> {
+    Expression(ExprBox<'a>),
+    // one entry per line, split on the `\` continuations.
+    Lines(Vec<&'a str>),
+}
+
+#[derive(Debug, Serialize)]
 pub struct Case<'a> {
     pub control_word: CaseType<'a>,
     pub comments_after_control_word: CommentsAndNewlines<'a>,
@@ -118,20 +145,20 @@ pub struct Case<'a> {
     pub statements: Vec<StmtBox<'a>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub enum CaseType<'a> {
     Case(ExprBox<'a>),
     Default,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct VariableDecl<'a> {
     pub var_expr: ExprBox<'a>,
     pub say_var: Option<Token<'a>>,
     pub say_var_comments: Option<CommentsAndNewlines<'a>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct DelimitedLine<'a, T> {
     pub expr: T,
     pub trailing_comment: CommentsAndNewlines<'a>,
@@ -0,0 +1,493 @@
+use super::error::FormatError;
+use super::lang_config::{LangConfig, Semicolons, TrailingCommas};
+use super::lex_token::{Token, TokenType};
+use super::modernizer::{Rewrite, RewriteKind};
+use super::scanner::Scanner;
+use std::fmt;
+
+/// Why we don't trust the output of a file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyError {
+    /// A comment in the input isn't in the output. Lines start at 1.
+    LostComment { line: u32, comment: String },
+    /// The input and output tokens differ, other than by whitespace and the rewrites the config asked for.
+    /// `input` and `output` are `None` when a file ran out of tokens first.
+    TokensDiffer {
+        input_line: u32,
+        input: Option<String>,
+        output_line: u32,
+        output: Option<String>,
+    },
+    /// Formatting the output again changed it, on this line of the output.
+    NotIdempotent { line: u32 },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_end = |token: &Option<String>| match token {
+            Some(token) => format!("`{}`", token),
+            None => "the end of the file".to_owned(),
+        };
+
+        match self {
+            VerifyError::LostComment { line, comment } => {
+                write!(
+                    f,
+                    "the comment `{}` on line {} is missing from the output",
+                    comment, line
+                )
+            }
+            VerifyError::TokensDiffer {
+                input_line,
+                input,
+                output_line,
+                output,
+            } => write!(
+                f,
+                "{} on line {} of the input became {} on line {} of the output",
+                or_end(input),
+                input_line,
+                or_end(output),
+                output_line
+            ),
+            VerifyError::NotIdempotent { line } => {
+                write!(f, "formatting the output again changes line {} of it", line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Why `format_checked` gave up on a source.
+#[derive(Debug)]
+pub enum CheckError {
+    /// The source, or the output of the first pass, could not be formatted.
+    Format(FormatError),
+    /// The second pass changed the output of the first. `line` starts at 1, and a line past
+    /// the end of a pass is empty.
+    NotIdempotent {
+        line: u32,
+        first_pass: String,
+        second_pass: String,
+    },
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckError::Format(e) => write!(f, "{}", e),
+            CheckError::NotIdempotent {
+                line,
+                first_pass,
+                second_pass,
+            } => write!(
+                f,
+                "formatting twice changes line {} from `{}` to `{}`",
+                line, first_pass, second_pass
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CheckError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CheckError::Format(e) => Some(e),
+            CheckError::NotIdempotent { .. } => None,
+        }
+    }
+}
+
+/// Fails with the first line where the two passes differ, if they do.
+pub(crate) fn check_second_pass(first_pass: &str, second_pass: &str) -> Result<(), CheckError> {
+    if first_pass == second_pass {
+        return Ok(());
+    }
+
+    let first_lines: Vec<&str> = first_pass.split('\n').collect();
+    let second_lines: Vec<&str> = second_pass.split('\n').collect();
+    let line = (0..first_lines.len().max(second_lines.len()))
+        .find(|i| first_lines.get(*i) != second_lines.get(*i))
+        .unwrap_or(0);
+    let line_of = |lines: &[&str]| {
+        lines
+            .get(line)
+            .map_or(String::new(), |text| text.trim_end_matches('\r').to_owned())
+    };
+
+    Err(CheckError::NotIdempotent {
+        line: line as u32 + 1,
+        first_pass: line_of(&first_lines),
+        second_pass: line_of(&second_lines),
+    })
+}
+
+/// Checks that `output` is `input` with only its whitespace changed, plus the rewrites
+/// `lang_config` allows, such as semicolons or braces added between statements. An `=`
+/// may only become `==` where `rewrites`, the modernizer's report, says it did that.
+/// `reformatted` should be `output` formatted again, which must be the same as `output`.
+pub(crate) fn verify(
+    input: &str,
+    output: &str,
+    reformatted: &str,
+    lang_config: &LangConfig,
+    rewrites: &[Rewrite],
+) -> Result<(), VerifyError> {
+    let input_tokens = significant_tokens(input);
+    let output_tokens = significant_tokens(output);
+
+    check_comments(&input_tokens, &output_tokens)?;
+    check_tokens(&input_tokens, &output_tokens, lang_config, rewrites)?;
+
+    if let Err(CheckError::NotIdempotent { line, .. }) = check_second_pass(output, reformatted) {
+        return Err(VerifyError::NotIdempotent { line });
+    }
+
+    Ok(())
+}
+
+// Everything but newlines, each with the text we compare it by. The printer owns the
+// whitespace inside macros, regions and comments, and writes numbers like `.5` in full,
+// so those are evened out.
+fn significant_tokens(source: &str) -> Vec<(Token<'_>, String)> {
+    Scanner::new(source)
+        .filter(|token| matches!(token.token_type, TokenType::Newline(_)) == false)
+        .map(|token| (token, comparison_text(&token)))
+        .collect()
+}
+
+fn comparison_text(token: &Token<'_>) -> String {
+    let without_whitespace = |text: &str| text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    match token.token_type {
+        // A macro's body is code too, so we compare its tokens.
+        TokenType::Macro(text) => {
+            let body = text
+                .trim_start_matches("#macro")
+                .replace("\\\r\n", " ")
+                .replace("\\\n", " ");
+            let body: Vec<String> = significant_tokens(&body).into_iter().map(|(_, text)| text).collect();
+            format!("#macro {}", body.join(" "))
+        }
+        TokenType::RegionBegin(text) | TokenType::RegionEnd(text) => without_whitespace(text),
+        TokenType::Comment(text) => format!("comment {}", without_whitespace(text)),
+        TokenType::MultilineComment(text) => format!("comment {}", without_whitespace(text)),
+        TokenType::String(text) => format!("string {}", text.replace("\r\n", "\n")),
+        TokenType::Number(text) => format!("number {}", text),
+        TokenType::NumberStartDot(text) => format!("number 0{}", text),
+        TokenType::NumberEndDot(text) => format!("number {}0", text),
+        TokenType::Identifier(text) => format!("identifier {}", text),
+        TokenType::UnidentifiedInput(text) => format!("unidentified {}", text),
+        other => format!("{:?}", other),
+    }
+}
+
+fn is_comment(token: &Token<'_>) -> bool {
+    matches!(token.token_type, TokenType::Comment(_) | TokenType::MultilineComment(_))
+}
+
+fn check_comments(input: &[(Token<'_>, String)], output: &[(Token<'_>, String)]) -> Result<(), VerifyError> {
+    let mut output_comments: Vec<&str> = output
+        .iter()
+        .filter(|(token, _)| is_comment(token))
+        .map(|(_, text)| text.as_str())
+        .collect();
+
+    for (token, text) in input.iter().filter(|(token, _)| is_comment(token)) {
+        match output_comments.iter().position(|comment| comment == text) {
+            Some(i) => {
+                output_comments.remove(i);
+            }
+            None => {
+                return Err(VerifyError::LostComment {
+                    line: token.line_number + 1,
+                    comment: describe(token),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Walks both files together. Where they differ, the input may have lost a token the config
+// lets us remove, or the output gained one the config lets us add.
+fn check_tokens(
+    input: &[(Token<'_>, String)],
+    output: &[(Token<'_>, String)],
+    lang_config: &LangConfig,
+    rewrites: &[Rewrite],
+) -> Result<(), VerifyError> {
+    let (mut i, mut o) = (0, 0);
+
+    while i < input.len() || o < output.len() {
+        let input_token = input.get(i);
+        let output_token = output.get(o);
+
+        match (input_token, output_token) {
+            (Some(a), Some(b)) if a.1 == b.1 || is_rewrite(&a.0, &b.0, lang_config, rewrites) => {
+                i += 1;
+                o += 1;
+            }
+            (Some(a), _) if may_remove(input, i, &a.0, lang_config) => i += 1,
+            (_, Some(b)) if may_add(output, o, &b.0, lang_config) => o += 1,
+            _ => {
+                let describe = |token: Option<&(Token<'_>, String)>| token.map(|(token, _)| describe(token));
+                let line = |tokens: &[(Token<'_>, String)], index: usize| {
+                    tokens
+                        .get(index)
+                        .or_else(|| tokens.last())
+                        .map_or(1, |(token, _)| token.line_number + 1)
+                };
+
+                return Err(VerifyError::TokensDiffer {
+                    input_line: line(input, i),
+                    input: describe(input_token),
+                    output_line: line(output, o),
+                    output: describe(output_token),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn is_rewrite(input: &Token<'_>, output: &Token<'_>, lang_config: &LangConfig, rewrites: &[Rewrite]) -> bool {
+    if lang_config.modernize_legacy_operators == false {
+        return false;
+    }
+
+    match (input.token_type, output.token_type) {
+        (TokenType::LessThanGreaterThan, TokenType::BangEqual) => true,
+        // Only in a condition, which the modernizer knows and we don't.
+        (TokenType::Equal, TokenType::EqualEqual) => rewrites.iter().any(|rewrite| {
+            rewrite.kind == RewriteKind::EqualInCondition
+                && (rewrite.line_number, rewrite.column_number) == (input.line_number, input.column_number)
+        }),
+        _ => false,
+    }
+}
+
+fn may_remove(tokens: &[(Token<'_>, String)], index: usize, token: &Token<'_>, lang_config: &LangConfig) -> bool {
+    match token.token_type {
+        // The last part of a `for` never keeps its semicolon.
+        TokenType::Semicolon => lang_config.semicolons == Semicolons::Never || closes_next(tokens, index),
+        TokenType::Then => lang_config.modernize_legacy_operators,
+        TokenType::Comma => lang_config.trailing_commas != TrailingCommas::Preserve && closes_next(tokens, index),
+        _ => false,
+    }
+}
+
+// Semicolons and braces may only be added between statements: a semicolon after the end
+// of one, before a line break or whatever closes it, an opening brace at the end of the
+// line it opens, and a closing brace on its own line, or before an `else` or `until`.
+fn may_add(tokens: &[(Token<'_>, String)], index: usize, token: &Token<'_>, lang_config: &LangConfig) -> bool {
+    let before = tokens[..index]
+        .iter()
+        .rev()
+        .find(|(token, _)| is_comment(token) == false);
+    let after = tokens[index + 1..].iter().find(|(token, _)| is_comment(token) == false);
+    let ends_line = match after {
+        Some((next, _)) => next.line_number > token.line_number,
+        None => true,
+    };
+
+    match token.token_type {
+        TokenType::Semicolon => {
+            lang_config.semicolons == Semicolons::Always
+                && matches!(before, Some((previous, _)) if ends_statement(previous))
+                && (ends_line
+                    || matches!(after, Some((next, _)) if matches!(next.token_type, TokenType::RightBrace | TokenType::Else)))
+        }
+        TokenType::LeftBrace => {
+            lang_config.insert_braces
+                && ends_line
+                && matches!(before, Some((previous, _))
+                    if ends_statement(previous) || matches!(previous.token_type, TokenType::Else | TokenType::Do))
+        }
+        TokenType::RightBrace => {
+            lang_config.insert_braces
+                && matches!(index.checked_sub(1), Some(previous) if tokens[previous].0.line_number < token.line_number)
+                && matches!(before, Some((previous, _))
+                    if ends_statement(previous) || previous.token_type == TokenType::Semicolon)
+                && (ends_line
+                    || matches!(after, Some((next, _)) if matches!(next.token_type, TokenType::Else | TokenType::Until)))
+        }
+        TokenType::Comma => lang_config.trailing_commas != TrailingCommas::Preserve && closes_next(tokens, index),
+        _ => false,
+    }
+}
+
+// Whether a statement can end with `token`, so a semicolon could follow it.
+fn ends_statement(token: &Token<'_>) -> bool {
+    matches!(
+        token.token_type,
+        TokenType::Identifier(_)
+            | TokenType::String(_)
+            | TokenType::Number(_)
+            | TokenType::NumberStartDot(_)
+            | TokenType::NumberEndDot(_)
+            | TokenType::RightParen
+            | TokenType::RightBracket
+            | TokenType::RightBrace
+            | TokenType::Incrementer
+            | TokenType::Decrementer
+            | TokenType::Break
+            | TokenType::Exit
+            | TokenType::Return
+    )
+}
+
+// Whether the token after `index` closes a list, so a comma at `index` is a trailing one.
+fn closes_next(tokens: &[(Token<'_>, String)], index: usize) -> bool {
+    let next = tokens[index + 1..].iter().find(|(token, _)| is_comment(token) == false);
+    matches!(
+        next,
+        Some((token, _)) if matches!(
+            token.token_type,
+            TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace
+        )
+    )
+}
+
+fn describe(token: &Token<'_>) -> String {
+    match token.token_type {
+        TokenType::Macro(text)
+        | TokenType::RegionBegin(text)
+        | TokenType::RegionEnd(text)
+        | TokenType::Identifier(text)
+        | TokenType::String(text)
+        | TokenType::Number(text)
+        | TokenType::NumberStartDot(text)
+        | TokenType::NumberEndDot(text)
+        | TokenType::Comment(text)
+        | TokenType::MultilineComment(text)
+        | TokenType::UnidentifiedInput(text) => text.to_owned(),
+        other => format!("{:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod verify_test {
+    use super::{check_second_pass, verify, CheckError, VerifyError};
+    use crate::lang_config::{LangConfig, Semicolons};
+    use crate::modernizer::{Rewrite, RewriteKind};
+
+    #[test]
+    fn allowed_changes() {
+        let lang_config = LangConfig::default();
+        let input = "#macro  A  f(1,2)\nvar x = .5 // half\nfor (i = 0; i < 2; i++;) {}";
+        let output = "#macro A f(1, 2)\nvar x = 0.5; // half\nfor (i = 0; i < 2; i++) {}\n";
+        assert_eq!(verify(input, output, output, &lang_config, &[]), Ok(()));
+
+        let lang_config = LangConfig {
+            semicolons: Semicolons::Preserve,
+            ..LangConfig::default()
+        };
+        assert!(verify(input, output, output, &lang_config, &[]).is_err());
+    }
+
+    #[test]
+    fn failures() {
+        let lang_config = LangConfig::default();
+
+        assert_eq!(
+            verify("a = 1; // one\n", "a = 1;\n", "a = 1;\n", &lang_config, &[]),
+            Err(VerifyError::LostComment {
+                line: 1,
+                comment: "// one".to_owned()
+            })
+        );
+        assert_eq!(
+            verify("a = b;\n", "a = c;\n", "a = c;\n", &lang_config, &[]),
+            Err(VerifyError::TokensDiffer {
+                input_line: 1,
+                input: Some("b".to_owned()),
+                output_line: 1,
+                output: Some("c".to_owned()),
+            })
+        );
+        assert_eq!(
+            verify("a = 1;\n", "a = 1;\n", "a = 1;\n\n", &lang_config, &[]),
+            Err(VerifyError::NotIdempotent { line: 3 })
+        );
+    }
+
+    #[test]
+    fn added_tokens_between_statements() {
+        let lang_config = LangConfig {
+            insert_braces: true,
+            ..LangConfig::default()
+        };
+        let input = "if (x) a()\nelse b = c\n";
+        let output = "if (x) {\n    a();\n} else {\n    b = c;\n}\n";
+        assert_eq!(verify(input, output, output, &lang_config, &[]), Ok(()));
+
+        // Not asked for.
+        assert!(verify(input, output, output, &LangConfig::default(), &[]).is_err());
+        let lang_config = LangConfig {
+            semicolons: Semicolons::Preserve,
+            ..lang_config
+        };
+        assert!(verify(input, output, output, &lang_config, &[]).is_err());
+
+        // In the middle of a statement.
+        let lang_config = LangConfig {
+            insert_braces: true,
+            ..LangConfig::default()
+        };
+        for output in [
+            "a = b; + c;\n",
+            "a = f({b + c});\n",
+            "a = f(b {\n    + c});\n",
+            "a = b\n} + c;\n",
+        ]
+        .iter()
+        {
+            assert!(
+                verify("a = b + c\n", output, output, &lang_config, &[]).is_err(),
+                "{}",
+                output
+            );
+        }
+    }
+
+    #[test]
+    fn equal_in_condition() {
+        let lang_config = LangConfig {
+            modernize_legacy_operators: true,
+            ..LangConfig::default()
+        };
+        let rewrites = [Rewrite {
+            kind: RewriteKind::EqualInCondition,
+            line_number: 0,
+            column_number: 6,
+        }];
+        let input = "if (a = b) c = d;\n";
+        let output = "if (a == b) c = d;\n";
+        assert_eq!(verify(input, output, output, &lang_config, &rewrites), Ok(()));
+
+        // Not in the condition, or not reported.
+        let output = "if (a == b) c == d;\n";
+        assert!(verify(input, output, output, &lang_config, &rewrites).is_err());
+        let output = "if (a == b) c = d;\n";
+        assert!(verify(input, output, output, &lang_config, &[]).is_err());
+        assert!(verify(input, output, output, &LangConfig::default(), &rewrites).is_err());
+    }
+
+    #[test]
+    fn second_pass() {
+        assert!(check_second_pass("a;\nb;\n", "a;\nb;\n").is_ok());
+
+        match check_second_pass("a;\r\nb;\r\n", "a;\r\nc;\r\n") {
+            Err(CheckError::NotIdempotent {
+                line,
+                first_pass,
+                second_pass,
+            }) => assert_eq!((line, first_pass.as_str(), second_pass.as_str()), (2, "b;", "c;")),
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,608 @@
+//! Walking the AST the parser builds.
+//!
+//! Implement `Visitor` to read a tree, or `VisitorMut` to change one. Each method
+//! walks into the children of what it is given by default, so override the ones you
+//! care about and call `walk_statement`, `walk_expr` (or their `_mut` forms) from them
+//! to keep going deeper. Children are visited in the order they appear in the source,
+//! along with every `CommentsAndNewlines` between them.
+
+use super::expressions::*;
+use super::statements::*;
+
+pub trait Visitor<'a> {
+    fn visit_statement(&mut self, stmt: &StatementWrapper<'a>) {
+        walk_statement(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &ExprBoxInterior<'a>) {
+        walk_expr(self, expr);
+    }
+
+    fn visit_comments(&mut self, _comments: &CommentsAndNewlines<'a>) {}
+}
+
+pub trait VisitorMut<'a> {
+    fn visit_statement_mut(&mut self, stmt: &mut StatementWrapper<'a>) {
+        walk_statement_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut ExprBoxInterior<'a>) {
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_comments_mut(&mut self, _comments: &mut CommentsAndNewlines<'a>) {}
+}
+
+/// Visits every statement in `ast`.
+pub fn walk_ast<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, ast: &[StmtBox<'a>]) {
+    for stmt in ast {
+        visitor.visit_statement(stmt);
+    }
+}
+
+pub fn walk_ast_mut<'a, V: VisitorMut<'a> + ?Sized>(visitor: &mut V, ast: &mut [StmtBox<'a>]) {
+    for stmt in ast {
+        visitor.visit_statement_mut(stmt);
+    }
+}
+
+pub fn walk_statement<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, stmt: &StatementWrapper<'a>) {
+    match &stmt.statement {
+        Statement::VariableDeclList {
+            comments_after_control_word,
+            var_decl,
+            ..
+        } => {
+            visitor.visit_comments(comments_after_control_word);
+            for line in &var_decl.lines {
+                if let Some(comments) = &line.expr.say_var_comments {
+                    visitor.visit_comments(comments);
+                }
+                visitor.visit_expr(&line.expr.var_expr);
+                visitor.visit_comments(&line.trailing_comment);
+            }
+        }
+        Statement::EnumDeclaration {
+            comments_after_control_word,
+            name,
+            comments_after_lbrace,
+            members,
+        } => {
+            visitor.visit_comments(comments_after_control_word);
+            visitor.visit_expr(name);
+            visitor.visit_comments(comments_after_lbrace);
+            walk_delimited_lines(visitor, members);
+        }
+        Statement::If {
+            comments_after_control_word,
+            condition,
+            then_branch,
+            comments_between,
+            comments_after_else,
+            else_branch,
+        } => {
+            visitor.visit_comments(comments_after_control_word);
+            visitor.visit_expr(condition);
+            visitor.visit_statement(then_branch);
+            visitor.visit_comments(comments_between);
+            visitor.visit_comments(comments_after_else);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_statement(else_branch);
+            }
+        }
+        Statement::WhileWithRepeat {
+            comments_after_control_word,
+            condition,
+            body,
+            ..
+        } => {
+            visitor.visit_comments(comments_after_control_word);
+            visitor.visit_expr(condition);
+            visitor.visit_statement(body);
+        }
+        Statement::DoUntil {
+            comments_after_control_word,
+            body,
+            comments_between,
+            condition,
+        } => {
+            visitor.visit_comments(comments_after_control_word);
+            visitor.visit_statement(body);
+            visitor.visit_comments(comments_between);
+            visitor.visit_expr(condition);
+        }
+        Statement::For {
+            comments_after_control_word,
+            comments_after_lparen,
+            initializer,
+            comments_after_initializer,
+            condition,
+            comments_after_condition,
+            increment,
+            comments_after_increment,
+            comments_after_rparen,
+            body,
+        } => {
+            visitor.visit_comments(comments_after_control_word);
+            visitor.visit_comments(comments_after_lparen);
+            if let Some(initializer) = initializer {
+                visitor.visit_statement(initializer);
+            }
+            visitor.visit_comments(comments_after_initializer);
+            if let Some(condition) = condition {
+                visitor.visit_expr(condition);
+            }
+            visitor.visit_comments(comments_after_condition);
+            if let Some(increment) = increment {
+                visitor.visit_expr(increment);
+            }
+            visitor.visit_comments(comments_after_increment);
+            visitor.visit_comments(comments_after_rparen);
+            visitor.visit_statement(body);
+        }
+        Statement::Switch {
+            comments_after_control_word,
+            condition,
+            comments_after_lbrace,
+            cases,
+        } => {
+            visitor.visit_comments(comments_after_control_word);
+            visitor.visit_expr(condition);
+            visitor.visit_comments(comments_after_lbrace);
+            for case in cases {
+                if let CaseType::Case(constant) = &case.control_word {
+                    visitor.visit_expr(constant);
+                }
+                visitor.visit_comments(&case.comments_after_control_word);
+                visitor.visit_comments(&case.comments_after_colon);
+                walk_ast(visitor, &case.statements);
+            }
+        }
+        Statement::ExpressionStatement { expression } => visitor.visit_expr(expression),
+        Statement::Block {
+            comments_after_lbrace,
+            statements,
+        } => {
+            visitor.visit_comments(comments_after_lbrace);
+            walk_ast(visitor, statements);
+        }
+        Statement::Return { expression } => {
+            if let Some(expression) = expression {
+                visitor.visit_expr(expression);
+            }
+        }
+        Statement::Define {
+            comments_after_control_word,
+            script_name,
+            body,
+        } => {
+            visitor.visit_comments(comments_after_control_word);
+            visitor.visit_expr(script_name);
+            walk_ast(visitor, body);
+        }
+        Statement::Macro {
+            definition:
+                Some(MacroDefinition {
+                    body: MacroBody::Expression(expression),
+                    ..
+                }),
+            ..
+        } => visitor.visit_expr(expression),
+        Statement::Macro { .. }
+        | Statement::Break
+        | Statement::Exit
+        | Statement::Comment { .. }
+        | Statement::MultilineComment { .. }
+        | Statement::RegionBegin(_)
+        | Statement::RegionEnd(_) => {}
+    }
+}
+
+pub fn walk_statement_mut<'a, V: VisitorMut<'a> + ?Sized>(visitor: &mut V, stmt: &mut StatementWrapper<'a>) {
+    match &mut stmt.statement {
+        Statement::VariableDeclList {
+            comments_after_control_word,
+            var_decl,
+            ..
+        } => {
+            visitor.visit_comments_mut(comments_after_control_word);
+            for line in &mut var_decl.lines {
+                if let Some(comments) = &mut line.expr.say_var_comments {
+                    visitor.visit_comments_mut(comments);
+                }
+                visitor.visit_expr_mut(&mut line.expr.var_expr);
+                visitor.visit_comments_mut(&mut line.trailing_comment);
+            }
+        }
+        Statement::EnumDeclaration {
+            comments_after_control_word,
+            name,
+            comments_after_lbrace,
+            members,
+        } => {
+            visitor.visit_comments_mut(comments_after_control_word);
+            visitor.visit_expr_mut(name);
+            visitor.visit_comments_mut(comments_after_lbrace);
+            walk_delimited_lines_mut(visitor, members);
+        }
+        Statement::If {
+            comments_after_control_word,
+            condition,
+            then_branch,
+            comments_between,
+            comments_after_else,
+            else_branch,
+        } => {
+            visitor.visit_comments_mut(comments_after_control_word);
+            visitor.visit_expr_mut(condition);
+            visitor.visit_statement_mut(then_branch);
+            visitor.visit_comments_mut(comments_between);
+            visitor.visit_comments_mut(comments_after_else);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_statement_mut(else_branch);
+            }
+        }
+        Statement::WhileWithRepeat {
+            comments_after_control_word,
+            condition,
+            body,
+            ..
+        } => {
+            visitor.visit_comments_mut(comments_after_control_word);
+            visitor.visit_expr_mut(condition);
+            visitor.visit_statement_mut(body);
+        }
+        Statement::DoUntil {
+            comments_after_control_word,
+            body,
+            comments_between,
+            condition,
+        } => {
+            visitor.visit_comments_mut(comments_after_control_word);
+            visitor.visit_statement_mut(body);
+            visitor.visit_comments_mut(comments_between);
+            visitor.visit_expr_mut(condition);
+        }
+        Statement::For {
+            comments_after_control_word,
+            comments_after_lparen,
+            initializer,
+            comments_after_initializer,
+            condition,
+            comments_after_condition,
+            increment,
+            comments_after_increment,
+            comments_after_rparen,
+            body,
+        } => {
+            visitor.visit_comments_mut(comments_after_control_word);
+            visitor.visit_comments_mut(comments_after_lparen);
+            if let Some(initializer) = initializer {
+                visitor.visit_statement_mut(initializer);
+            }
+            visitor.visit_comments_mut(comments_after_initializer);
+            if let Some(condition) = condition {
+                visitor.visit_expr_mut(condition);
+            }
+            visitor.visit_comments_mut(comments_after_condition);
+            if let Some(increment) = increment {
+                visitor.visit_expr_mut(increment);
+            }
+            visitor.visit_comments_mut(comments_after_increment);
+            visitor.visit_comments_mut(comments_after_rparen);
+            visitor.visit_statement_mut(body);
+        }
+        Statement::Switch {
+            comments_after_control_word,
+            condition,
+            comments_after_lbrace,
+            cases,
+        } => {
+            visitor.visit_comments_mut(comments_after_control_word);
+            visitor.visit_expr_mut(condition);
+            visitor.visit_comments_mut(comments_after_lbrace);
+            for case in cases {
+                if let CaseType::Case(constant) = &mut case.control_word {
+                    visitor.visit_expr_mut(constant);
+                }
+                visitor.visit_comments_mut(&mut case.comments_after_control_word);
+                visitor.visit_comments_mut(&mut case.comments_after_colon);
+                walk_ast_mut(visitor, &mut case.statements);
+            }
+        }
+        Statement::ExpressionStatement { expression } => visitor.visit_expr_mut(expression),
+        Statement::Block {
+            comments_after_lbrace,
+            statements,
+        } => {
+            visitor.visit_comments_mut(comments_after_lbrace);
+            walk_ast_mut(visitor, statements);
+        }
+        Statement::Return { expression } => {
+            if let Some(expression) = expression {
+                visitor.visit_expr_mut(expression);
+            }
+        }
+        Statement::Define {
+            comments_after_control_word,
+            script_name,
+            body,
+        } => {
+            visitor.visit_comments_mut(comments_after_control_word);
+            visitor.visit_expr_mut(script_name);
+            walk_ast_mut(visitor, body);
+        }
+        Statement::Macro {
+            definition:
+                Some(MacroDefinition {
+                    body: MacroBody::Expression(expression),
+                    ..
+                }),
+            ..
+        } => visitor.visit_expr_mut(expression),
+        Statement::Macro { .. }
+        | Statement::Break
+        | Statement::Exit
+        | Statement::Comment { .. }
+        | Statement::MultilineComment { .. }
+        | Statement::RegionBegin(_)
+        | Statement::RegionEnd(_) => {}
+    }
+}
+
+pub fn walk_expr<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, expr: &ExprBoxInterior<'a>) {
+    match &expr.expr {
+        Expr::Call {
+            procedure_name,
+            comments_and_newlines_after_lparen,
+            arguments,
+        } => {
+            visitor.visit_expr(procedure_name);
+            visitor.visit_comments(comments_and_newlines_after_lparen);
+            walk_delimited_lines(visitor, arguments);
+        }
+        Expr::Function {
+            comments_after_control_word,
+            call,
+            comments_after_rparen,
+            ..
+        } => {
+            visitor.visit_comments(comments_after_control_word);
+            visitor.visit_expr(call);
+            visitor.visit_comments(comments_after_rparen);
+        }
+        Expr::StructOperator {
+            comments_before_expression,
+            expression,
+            ..
+        } => {
+            visitor.visit_comments(comments_before_expression);
+            visitor.visit_expr(expression);
+        }
+        Expr::Binary {
+            left,
+            comments_and_newlines_between_op_and_r,
+            right,
+            ..
+        }
+        | Expr::Assign {
+            left,
+            comments_and_newlines_between_op_and_r,
+            right,
+            ..
+        } => {
+            visitor.visit_expr(left);
+            visitor.visit_comments(comments_and_newlines_between_op_and_r);
+            visitor.visit_expr(right);
+        }
+        Expr::Grouping {
+            comments_and_newlines_after_lparen,
+            expressions,
+            comments_and_newlines_after_rparen,
+        } => {
+            visitor.visit_comments(comments_and_newlines_after_lparen);
+            for expression in expressions {
+                visitor.visit_expr(expression);
+            }
+            visitor.visit_comments(comments_and_newlines_after_rparen);
+        }
+        Expr::ArrayLiteral {
+            comments_and_newlines_after_lbracket,
+            arguments,
+        } => {
+            visitor.visit_comments(comments_and_newlines_after_lbracket);
+            walk_delimited_lines(visitor, arguments);
+        }
+        Expr::Literal { comments, .. }
+        | Expr::NumberStartDot { comments, .. }
+        | Expr::NumberEndDot { comments, .. }
+        | Expr::Identifier { comments, .. } => visitor.visit_comments(comments),
+        Expr::Unary {
+            comments_and_newlines_between,
+            right,
+            ..
+        } => {
+            visitor.visit_comments(comments_and_newlines_between);
+            visitor.visit_expr(right);
+        }
+        Expr::Postfix {
+            comments_and_newlines_between,
+            expr,
+            ..
+        } => {
+            visitor.visit_expr(expr);
+            visitor.visit_comments(comments_and_newlines_between);
+        }
+        Expr::DotAccess {
+            object_name,
+            comments_between,
+            instance_variable,
+        } => {
+            visitor.visit_expr(object_name);
+            visitor.visit_comments(comments_between);
+            visitor.visit_expr(instance_variable);
+        }
+        Expr::DataStructureAccess {
+            ds_name, access_exprs, ..
+        } => {
+            visitor.visit_expr(ds_name);
+            for (comments, access_expr) in access_exprs {
+                visitor.visit_comments(comments);
+                visitor.visit_expr(access_expr);
+            }
+        }
+        Expr::Ternary {
+            conditional,
+            comments_and_newlines_after_q,
+            left,
+            comments_and_newlines_after_colon,
+            right,
+        } => {
+            visitor.visit_expr(conditional);
+            visitor.visit_comments(comments_and_newlines_after_q);
+            visitor.visit_expr(left);
+            visitor.visit_comments(comments_and_newlines_after_colon);
+            visitor.visit_expr(right);
+        }
+        Expr::Newline | Expr::Comment { .. } | Expr::MultilineComment { .. } | Expr::UnidentifiedAsLiteral { .. } => {}
+    }
+
+    visitor.visit_comments(&expr.trailing_comments);
+}
+
+pub fn walk_expr_mut<'a, V: VisitorMut<'a> + ?Sized>(visitor: &mut V, expr: &mut ExprBoxInterior<'a>) {
+    match &mut expr.expr {
+        Expr::Call {
+            procedure_name,
+            comments_and_newlines_after_lparen,
+            arguments,
+        } => {
+            visitor.visit_expr_mut(procedure_name);
+            visitor.visit_comments_mut(comments_and_newlines_after_lparen);
+            walk_delimited_lines_mut(visitor, arguments);
+        }
+        Expr::Function {
+            comments_after_control_word,
+            call,
+            comments_after_rparen,
+            ..
+        } => {
+            visitor.visit_comments_mut(comments_after_control_word);
+            visitor.visit_expr_mut(call);
+            visitor.visit_comments_mut(comments_after_rparen);
+        }
+        Expr::StructOperator {
+            comments_before_expression,
+            expression,
+            ..
+        } => {
+            visitor.visit_comments_mut(comments_before_expression);
+            visitor.visit_expr_mut(expression);
+        }
+        Expr::Binary {
+            left,
+            comments_and_newlines_between_op_and_r,
+            right,
+            ..
+        }
+        | Expr::Assign {
+            left,
+            comments_and_newlines_between_op_and_r,
+            right,
+            ..
+        } => {
+            visitor.visit_expr_mut(left);
+            visitor.visit_comments_mut(comments_and_newlines_between_op_and_r);
+            visitor.visit_expr_mut(right);
+        }
+        Expr::Grouping {
+            comments_and_newlines_after_lparen,
+            expressions,
+            comments_and_newlines_after_rparen,
+        } => {
+            visitor.visit_comments_mut(comments_and_newlines_after_lparen);
+            for expression in expressions {
+                visitor.visit_expr_mut(expression);
+            }
+            visitor.visit_comments_mut(comments_and_newlines_after_rparen);
+        }
+        Expr::ArrayLiteral {
+            comments_and_newlines_after_lbracket,
+            arguments,
+        } => {
+            visitor.visit_comments_mut(comments_and_newlines_after_lbracket);
+            walk_delimited_lines_mut(visitor, arguments);
+        }
+        Expr::Literal { comments, .. }
+        | Expr::NumberStartDot { comments, .. }
+        | Expr::NumberEndDot { comments, .. }
+        | Expr::Identifier { comments, .. } => visitor.visit_comments_mut(comments),
+        Expr::Unary {
+            comments_and_newlines_between,
+            right,
+            ..
+        } => {
+            visitor.visit_comments_mut(comments_and_newlines_between);
+            visitor.visit_expr_mut(right);
+        }
+        Expr::Postfix {
+            comments_and_newlines_between,
+            expr,
+            ..
+        } => {
+            visitor.visit_expr_mut(expr);
+            visitor.visit_comments_mut(comments_and_newlines_between);
+        }
+        Expr::DotAccess {
+            object_name,
+            comments_between,
+            instance_variable,
+        } => {
+            visitor.visit_expr_mut(object_name);
+            visitor.visit_comments_mut(comments_between);
+            visitor.visit_expr_mut(instance_variable);
+        }
+        Expr::DataStructureAccess {
+            ds_name, access_exprs, ..
+        } => {
+            visitor.visit_expr_mut(ds_name);
+            for (comments, access_expr) in access_exprs {
+                visitor.visit_comments_mut(comments);
+                visitor.visit_expr_mut(access_expr);
+            }
+        }
+        Expr::Ternary {
+            conditional,
+            comments_and_newlines_after_q,
+            left,
+            comments_and_newlines_after_colon,
+            right,
+        } => {
+            visitor.visit_expr_mut(conditional);
+            visitor.visit_comments_mut(comments_and_newlines_after_q);
+            visitor.visit_expr_mut(left);
+            visitor.visit_comments_mut(comments_and_newlines_after_colon);
+            visitor.visit_expr_mut(right);
+        }
+        Expr::Newline | Expr::Comment { .. } | Expr::MultilineComment { .. } | Expr::UnidentifiedAsLiteral { .. } => {}
+    }
+
+    visitor.visit_comments_mut(&mut expr.trailing_comments);
+}
+
+fn walk_delimited_lines<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, lines: &DelimitedLines<'a, ExprBox<'a>>) {
+    for line in &lines.lines {
+        visitor.visit_expr(&line.expr);
+        visitor.visit_comments(&line.trailing_comment);
+    }
+}
+
+fn walk_delimited_lines_mut<'a, V: VisitorMut<'a> + ?Sized>(
+    visitor: &mut V,
+    lines: &mut DelimitedLines<'a, ExprBox<'a>>,
+) {
+    for line in &mut lines.lines {
+        visitor.visit_expr_mut(&mut line.expr);
+        visitor.visit_comments_mut(&mut line.trailing_comment);
+    }
+}
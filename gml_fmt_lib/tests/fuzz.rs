@@ -0,0 +1,103 @@
+//! Feeds generated input to `run_snippet`, and fails if it panics or doesn't finish. Set
+//! `GML_FMT_FUZZ_RUNS` to run for longer, and `GML_FMT_FUZZ_SEED` to start from a
+//! different seed; a failure prints the seed which reproduces it.
+
+use gml_fmt_lib::*;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_RUNS: u64 = 2000;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Pieces of GML, so the input gets further into the parser than random bytes do.
+const WORDS: &str = "var globalvar if else then while do until repeat for with switch case default : break exit \
+    return function constructor new delete enum #macro #define #region #endregion begin end and or not div mod \
+    ( ) { } [ ] [@ [| [? [# [$ ; , . ? = == != <> + - * / += ++ -- ! ~ & | ^ << >> && || < > <= >= \
+    a b_1 self 1 2.5 $ff 0x1F \"text\" @\"raw\" 'single' /* \" \\ é";
+const TRIVIA: &[&str] = &[" ", "\t", "\n", "\r\n", "\u{feff}", "// comment", "/* multi */"];
+
+// xorshift64*, so runs can be repeated without a crate for random numbers.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn random_bytes(rng: &mut Rng) -> String {
+    let bytes: Vec<u8> = (0..rng.below(64)).map(|_| rng.next() as u8).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn random_fragments(rng: &mut Rng, fragments: &[&str]) -> String {
+    let mut source = String::new();
+    for _ in 0..rng.below(200) {
+        source.push_str(fragments[rng.below(fragments.len())]);
+        if rng.below(3) == 0 {
+            source.push(' ');
+        }
+    }
+    source
+}
+
+fn env_number(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn check(source: String, config: Option<LangConfig>, seed: u64) {
+    let (sender, receiver) = mpsc::channel();
+    let input = source.clone();
+    thread::spawn(move || {
+        // A panic drops the sender, so it shows up as a disconnect.
+        let _ = run_snippet(&input, config);
+        let _ = sender.send(());
+    });
+
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(()) => {}
+        Err(mpsc::RecvTimeoutError::Disconnected) => panic!("seed {} panicked on {:?}", seed, source),
+        Err(mpsc::RecvTimeoutError::Timeout) => panic!("seed {} did not finish on {:?}", seed, source),
+    }
+}
+
+#[test]
+fn run_snippet_never_panics_or_hangs() {
+    let first_seed = env_number("GML_FMT_FUZZ_SEED", 1);
+    let fragments: Vec<&str> = WORDS.split_whitespace().chain(TRIVIA.iter().copied()).collect();
+    let configs = [
+        LangConfig::default(),
+        LangConfig::builder()
+            .use_spaces(false)
+            .modernize_legacy_operators(true)
+            .insert_braces(true)
+            .semicolons(Semicolons::Never)
+            .trailing_commas(TrailingCommas::Always)
+            .end_of_line(EndOfLine::Crlf)
+            .build()
+            .unwrap(),
+    ];
+
+    for seed in first_seed..first_seed + env_number("GML_FMT_FUZZ_RUNS", DEFAULT_RUNS) {
+        // Zero would stay zero.
+        let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+        let source = if rng.below(4) == 0 {
+            random_bytes(&mut rng)
+        } else {
+            random_fragments(&mut rng, &fragments)
+        };
+        let config = configs[rng.below(configs.len())].clone();
+        check(source, Some(config), seed);
+    }
+}
@@ -1,15 +1,79 @@
 use gml_fmt_lib::*;
+use std::fs;
+use std::path::PathBuf;
 
 const LANG_CONFIG: LangConfig = LangConfig {
     use_spaces: true,
     space_size: 4,
     newlines_at_end: 1,
+    modernize_legacy_operators: false,
+    insert_braces: false,
+    semicolons: Semicolons::Always,
+    trailing_commas: TrailingCommas::Preserve,
+    end_of_line: EndOfLine::Lf,
+    max_depth: DEFAULT_MAX_DEPTH,
+};
+
+const MODERNIZE_CONFIG: LangConfig = LangConfig {
+    modernize_legacy_operators: true,
+    ..LANG_CONFIG
+};
+
+const BRACES_CONFIG: LangConfig = LangConfig {
+    insert_braces: true,
+    ..LANG_CONFIG
+};
+
+const NO_SEMICOLONS_CONFIG: LangConfig = LangConfig {
+    semicolons: Semicolons::Never,
+    ..LANG_CONFIG
+};
+
+const PRESERVE_SEMICOLONS_CONFIG: LangConfig = LangConfig {
+    semicolons: Semicolons::Preserve,
+    ..LANG_CONFIG
 };
 
 fn run_test(input: &str) -> String {
     run(input, &LANG_CONFIG, None).expect("Panicked during Integration Test!")
 }
 
+fn run_test_with(input: &str, lang_config: &LangConfig) -> String {
+    run(input, lang_config, None).expect("Panicked during Integration Test!")
+}
+
+// A folder of files for one test, which is removed when the test ends, whether it passed or not.
+struct TempProject {
+    root: PathBuf,
+}
+
+impl TempProject {
+    fn new(name: &str) -> TempProject {
+        let root = std::env::temp_dir().join(format!("gml_fmt_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        TempProject { root }
+    }
+
+    fn path(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+
+    // Writes `contents` to `path`, making the folders it goes in.
+    fn write(&self, path: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.path(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempProject {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
 #[test]
 fn regions() {
     let input = "#region Test Test  Test
@@ -296,6 +360,102 @@ fn not_trailing_commas() {
     assert_eq!(run_test(input), output);
 }
 
+#[test]
+fn trailing_commas_always() {
+    let config = LangConfig {
+        trailing_commas: TrailingCommas::Always,
+        ..LANG_CONFIG
+    };
+    let input = "func(a, b);
+func(a, b,);
+func(
+    a,
+    b
+);
+arr = [1, 2];
+arr = [
+    1,
+    2
+];
+enum E { A, B }
+function foo(a, b) {}
+var x = 1, y = 2;
+";
+    let output = "func(a, b);
+func(a, b,);
+func(
+    a,
+    b,
+);
+arr = [1, 2];
+arr = [
+    1,
+    2,
+];
+enum E {
+    A,
+    B,
+}
+function foo(a, b) {}
+var x = 1, y = 2;
+";
+
+    assert_eq!(run_test_with(input, &config), output);
+}
+
+#[test]
+fn trailing_commas_never() {
+    let config = LangConfig {
+        trailing_commas: TrailingCommas::Never,
+        ..LANG_CONFIG
+    };
+    let input = "func(a, b,);
+arr = [
+    1,
+    2,
+];
+enum E { A, B, }
+";
+    let output = "func(a, b);
+arr = [
+    1,
+    2
+];
+enum E {
+    A,
+    B
+}
+";
+
+    assert_eq!(run_test_with(input, &config), output);
+}
+
+#[test]
+fn trailing_commas_multiline() {
+    let config = LangConfig {
+        trailing_commas: TrailingCommas::Multiline,
+        ..LANG_CONFIG
+    };
+    let input = "func(a, b,);
+func(
+    a,
+    b
+);
+arr = [1, 2,];
+var x = 1, y = 2,;
+";
+    let output = "func(a, b);
+func(
+    a,
+    b,
+);
+arr = [1, 2];
+var x = 1, y = 2;
+";
+
+    assert_eq!(run_test_with(input, &config), output);
+}
+
 #[test]
 fn enum_test() {
     let input = "enum EInputConstants{//Starts at high negative number to not interfere with other input constants
@@ -551,6 +711,87 @@ fn nice_macro() {
     assert_eq!(run_test(input), output);
 }
 
+#[test]
+fn macro_expression_body() {
+    let input = "#macro   SCREEN_W    1920
+#macro Desktop:COLOR   make_colour_rgb(255,0,0)
+#macro FIVE 5 // five
+";
+    let output = "#macro SCREEN_W 1920
+#macro Desktop:COLOR make_colour_rgb(255, 0, 0)
+#macro FIVE 5 // five
+";
+
+    assert_eq!(run_test(input), output);
+}
+
+#[test]
+fn macro_continuation_lines() {
+    let input = "#macro BAR a + \\
+      b +  \\
+   c
+if (x) {
+    #macro M a( \\\r\nb)
+}
+#macro ARR [1,\\
+2,   \\
+    3]
+#macro F function() { \\
+    a(); \\
+    b(); \\
+}
+";
+    let output = "#macro BAR a + \\
+           b + \\
+           c
+if (x) {
+    #macro M a( \\
+             b)
+}
+#macro ARR [1, \\
+           2, \\
+           3]
+#macro F function() { \\
+         a(); \\
+         b(); \\
+         }
+";
+
+    assert_eq!(run_test(input), output);
+}
+
+#[test]
+fn macro_continuation_lines_with_comments() {
+    let input = "#macro BAR a + \\
+      b +  /* bee */ \\
+   c
+#macro S @\"one  \\
+  two\"
+";
+    let output = "#macro BAR a + \\
+           b +  /* bee */ \\
+           c
+#macro S @\"one  \\
+  two\"
+";
+
+    assert_eq!(run_test(input), output);
+}
+
+#[test]
+fn macro_verbatim_fallback() {
+    let input = "#macro EMPTY
+#macro 3bad  stuff
+#macro STATEMENT if (a)   b();
+";
+    let output = "#macro EMPTY
+#macro 3bad  stuff
+#macro STATEMENT if (a)   b();
+";
+
+    assert_eq!(run_test(input), output);
+}
+
 #[test]
 fn whitesmith_enum() {
     let input = "enum YosiFunction
@@ -641,3 +882,1280 @@ audio_stop_sound(aMusicTitle);
 
     assert_eq!(run_test(input), output);
 }
+
+#[test]
+fn semicolons_never() {
+    let input = "call(z);
+var a = 1, b = 2;
+if (x) { foo(); }
+while (y) y--;
+do { y++; } until (y > 3);
+for (var i = 0; i < 3; i++) { show(i); }
+switch (x) {
+    case 1: a(); break;
+}
+enum E { A, B };
+exit;
+";
+    let output = "call(z)
+var a = 1, b = 2
+if (x) { foo() }
+while (y) y--
+do {
+    y++
+} until (y > 3)
+for (var i = 0; i < 3; i++) { show(i) }
+switch (x) {
+    case 1:
+        a()
+        break
+}
+enum E {
+    A,
+    B
+}
+exit
+";
+
+    assert_eq!(run_test_with(input, &NO_SEMICOLONS_CONFIG), output);
+    assert_eq!(run_test_with(output, &NO_SEMICOLONS_CONFIG), output);
+}
+
+#[test]
+fn semicolons_never_is_idempotent() {
+    let input = "if (a) {
+    b();
+}
+with (c) {
+    d(); // e
+}
+if (f) { g(); }
+h = 1; // i
+switch (j) {
+    case 1:
+        k(); /* l */
+        break;
+}
+";
+    let output = "if (a) {
+    b()
+}
+with (c) {
+    d() // e
+}
+if (f) { g() }
+h = 1 // i
+switch (j) {
+    case 1:
+        k() /* l */
+        break
+}
+";
+
+    let once = run_test_with(input, &NO_SEMICOLONS_CONFIG);
+    assert_eq!(once, output);
+    assert_eq!(run_test_with(&once, &NO_SEMICOLONS_CONFIG), output);
+}
+
+#[test]
+fn semicolons_never_keeps_statements_apart() {
+    let input = "a = b;
+(c)();
+x = 1;
+// note
+-y;
+w = 2;
+[1, 2];
+var i = 0;
+++i;
+if (t) {
+    return;
+    foo();
+}
+";
+    let output = "a = b;
+(c)()
+x = 1;
+// note
+-y
+w = 2;
+[1, 2]
+var i = 0;
+++i
+if (t) {
+    return;
+    foo()
+}
+";
+
+    assert_eq!(run_test_with(input, &NO_SEMICOLONS_CONFIG), output);
+}
+
+#[test]
+fn semicolons_preserve() {
+    let input = "global.roundOver=true
+alarm[3]=room_speed/10;
+var a = 1
+var b = 2;
+";
+    let output = "global.roundOver = true
+alarm[3] = room_speed / 10;
+var a = 1
+var b = 2;
+";
+
+    assert_eq!(run_test_with(input, &PRESERVE_SEMICOLONS_CONFIG), output);
+}
+
+#[test]
+fn legacy_operators_untouched_by_default() {
+    let input = "if a <> b then c = 1;
+";
+
+    assert_eq!(run_test(input), input);
+}
+
+#[test]
+fn modernize_legacy_operators() {
+    let input = "if (a = b) then c = 1;
+if a <> b then {
+foo();
+}
+while (x = 2) x -= 1;
+do { y++; } until (y = 10);
+if (a = 1 && b <> 2) then exit;
+";
+
+    let output = "if (a == b) c = 1;
+if a != b {
+    foo();
+}
+while (x == 2) x -= 1;
+
+do {
+    y++;
+} until (y == 10);
+if (a == 1 && b != 2) exit;
+";
+
+    assert_eq!(run_test_with(input, &MODERNIZE_CONFIG), output);
+}
+
+#[test]
+fn modernize_leaves_assignments_alone() {
+    let input = "a = b;
+if (foo(c = d)) e = f;
+";
+
+    assert_eq!(run_test_with(input, &MODERNIZE_CONFIG), input);
+}
+
+#[test]
+fn allman_else() {
+    let input = "if (x)
+{
+    a();
+}
+else
+{
+    b();
+}
+if (x) a();
+else
+    b();
+";
+    let output = "if (x) {
+    a();
+} else {
+    b();
+}
+if (x) a(); else
+    b();
+";
+
+    assert_eq!(run_test(input), output);
+}
+
+#[test]
+fn line_comment_before_else() {
+    let input = "if (x) a(); // c
+else b();
+";
+
+    assert_eq!(run_test(input), input);
+}
+
+#[test]
+fn trailing_comment_in_block() {
+    let input = "if (x) {
+    foo(); // trailing
+    bar(); /* also trailing */
+}
+";
+
+    assert_eq!(run_test(input), input);
+}
+
+#[test]
+fn insert_braces() {
+    let input = "if (x) foo(); else bar();
+while (x) x--;
+with (obj) instance_destroy();
+repeat (3) show_debug_message(\"x\");
+for (var i = 0; i < 3; i++) show(i);
+do x++; until (x > 3);
+if (x) { foo(); }
+";
+    let output = "if (x) {
+    foo();
+} else {
+    bar();
+}
+while (x) {
+    x--;
+}
+with (obj) {
+    instance_destroy();
+}
+repeat (3) {
+    show_debug_message(\"x\");
+}
+for (var i = 0; i < 3; i++) {
+    show(i);
+}
+do {
+    x++;
+} until (x > 3);
+if (x) { foo(); }
+";
+
+    assert_eq!(run_test_with(input, &BRACES_CONFIG), output);
+}
+
+#[test]
+fn insert_braces_keeps_else_if_flat() {
+    let input = "if (x) a(); else if (y) b(); else c();
+";
+    let output = "if (x) {
+    a();
+} else if (y) {
+    b();
+} else {
+    c();
+}
+";
+
+    assert_eq!(run_test_with(input, &BRACES_CONFIG), output);
+}
+
+#[test]
+fn insert_braces_keeps_comments() {
+    let input = "if (x) // why
+    foo(); // trailing
+else // because
+    bar();
+for (;;) // forever
+    step();
+if (x)
+    // leading
+    foo();
+";
+    let output = "if (x) { // why
+    foo(); // trailing
+} else { // because
+    bar();
+}
+for (;;) { // forever
+    step();
+}
+if (x) {
+    // leading
+    foo();
+}
+";
+
+    assert_eq!(run_test_with(input, &BRACES_CONFIG), output);
+}
+
+#[test]
+fn insert_braces_keeps_trailing_comments_on_their_line() {
+    let input = "while (x) y(); // t2
+z();
+for (var i = 0; i < 3; i++) f(i); // c1
+g();
+if (a) b(); else c(); // c2
+h();
+repeat (2) k(); /* c3 */ // c4
+m();
+with (obj) n(); // c5
+do x++; // c6
+until (x > 3);
+if (a) while (b) c(); // c7
+if (a) b(); else if (c) d(); else while (e) f(); // c8
+switch (a) {
+    case 1:
+        while (x) y(); // c9
+        break;
+}
+";
+    let output = "while (x) {
+    y(); // t2
+}
+z();
+for (var i = 0; i < 3; i++) {
+    f(i); // c1
+}
+g();
+if (a) {
+    b();
+} else {
+    c(); // c2
+}
+h();
+repeat (2) {
+    k(); /* c3 */ // c4
+}
+m();
+with (obj) {
+    n(); // c5
+}
+do {
+    x++; // c6
+} until (x > 3);
+if (a) {
+    while (b) {
+        c(); // c7
+    }
+}
+if (a) {
+    b();
+} else if (c) {
+    d();
+} else {
+    while (e) {
+        f(); // c8
+    }
+}
+switch (a) {
+    case 1:
+        while (x) {
+            y(); // c9
+        }
+        break;
+}
+";
+
+    assert_eq!(run_test_with(input, &BRACES_CONFIG), output);
+    assert_eq!(run_test_with(output, &BRACES_CONFIG), output);
+}
+
+#[test]
+fn config_discovery() {
+    let temp = TempProject::new("config_discovery");
+    temp.write("gml_fmt.toml", "space_size = 8\nuse_spaces = false\n");
+    temp.write("project/project.yyp", "");
+    temp.write("project/gml_fmt.toml", "space_size = 2\n");
+    temp.write("project/scripts/.gml_fmt.toml", "newlines_at_end = 2\n");
+    let outside = temp.write("a.gml", "");
+    let script = temp.write("project/scripts/a.gml", "");
+    let nested = temp.write("project/scripts/nested/a.gml", "");
+
+    let outside = LangConfig::for_file(&outside, &LANG_CONFIG).unwrap();
+    assert_eq!((outside.space_size, outside.use_spaces), (8, false));
+
+    // nearer files win, and the walk stops at the project folder.
+    let nested_config = LangConfig::for_file(&nested, &LANG_CONFIG).unwrap();
+    assert_eq!(
+        (
+            nested_config.space_size,
+            nested_config.use_spaces,
+            nested_config.newlines_at_end
+        ),
+        (2, true, 2)
+    );
+
+    temp.write("project/scripts/.gml_fmt.toml", "root = true\nnewlines_at_end = 2\n");
+    let root_config = LangConfig::for_file(&script, &LANG_CONFIG).unwrap();
+    assert_eq!((root_config.space_size, root_config.newlines_at_end), (4, 2));
+}
+
+#[test]
+fn config_errors() {
+    let temp = TempProject::new("config_errors");
+    let file = temp.write("a.gml", "");
+
+    let error_for = |contents: &str| {
+        let config_path = temp.write("gml_fmt.toml", contents);
+        let error = LangConfig::for_file(&file, &LANG_CONFIG).unwrap_err();
+        assert_eq!(error.path.as_ref(), Some(&config_path));
+        error.to_string()[config_path.display().to_string().len()..].to_owned()
+    };
+
+    assert_eq!(
+        error_for("use_spaces = true\nspace_size = \"4\"\n"),
+        ":2: invalid value for `space_size`: invalid type: string \"4\", expected usize"
+    );
+    assert_eq!(
+        error_for("spaces_size = 4\n"),
+        ":1: unknown option `spaces_size`, did you mean `space_size`?"
+    );
+    assert_eq!(error_for("colour = \"red\"\n"), ":1: unknown option `colour`");
+    assert_eq!(
+        error_for("max_line_length = 100\n"),
+        ":1: unknown option `max_line_length`"
+    );
+    assert_eq!(
+        error_for("space_size = 0\n"),
+        ":1: `space_size` must be between 1 and 16, but it is 0"
+    );
+    assert_eq!(
+        error_for("newlines_at_end = 99\n"),
+        ":1: `newlines_at_end` must be between 0 and 10, but it is 99"
+    );
+    assert_eq!(
+        error_for("space_size = 4\nuse_spaces\n"),
+        ":2: expected an equals, found a newline"
+    );
+}
+
+#[test]
+fn config_overrides() {
+    let temp = TempProject::new("config_overrides");
+    let file = temp.write("a.gml", "");
+    let config_file = temp.write("gml_fmt.toml", "space_size = 2\nnewlines_at_end = 2\n");
+    let other_config_file = temp.write("other.toml", "use_spaces = false\n");
+
+    let mut config_resolver = ConfigResolver::default();
+    config_resolver.add_override("space_size=3").unwrap();
+    config_resolver.add_override("semicolons = never").unwrap();
+    assert!(config_resolver.add_override("semicolons").is_err());
+    assert!(config_resolver.add_override("space_size=0").is_err());
+
+    let resolved = config_resolver.resolve(&file).unwrap();
+    assert_eq!(resolved.lang_config.space_size, 3);
+    assert_eq!(resolved.lang_config.newlines_at_end, 2);
+    assert_eq!(resolved.lang_config.semicolons, Semicolons::Never);
+    assert_eq!(resolved.sources["space_size"], ConfigSource::CommandLine);
+
+    let config_file = config_file.canonicalize().unwrap();
+    assert_eq!(
+        resolved.sources["newlines_at_end"],
+        ConfigSource::File(config_file.clone())
+    );
+    assert!(resolved
+        .to_toml()
+        .contains(&format!("newlines_at_end = 2 # {}\n", config_file.display())));
+    assert!(resolved.to_toml().contains("use_spaces = true # default\n"));
+
+    // an explicit config file stands in for the ones we would have found.
+    config_resolver.set_config_file(&other_config_file).unwrap();
+    let resolved = config_resolver.resolve(&file).unwrap();
+    assert_eq!(resolved.lang_config.newlines_at_end, 1);
+    assert!(!resolved.lang_config.use_spaces);
+    assert_eq!(resolved.lang_config.space_size, 3);
+}
+
+#[test]
+fn end_of_line_crlf() {
+    let config = LangConfig {
+        end_of_line: EndOfLine::Crlf,
+        ..LANG_CONFIG
+    };
+    let input = "/* one\r\ntwo\nthree */\r\nvar a = 1;\nb = @\"x\r\ny\nz\";\n";
+    let format = "/* one\r\ntwo\r\nthree */\r\nvar a = 1;\r\nb = @\"x\r\ny\nz\";\r\n";
+
+    assert_eq!(run_test_with(input, &config), format);
+}
+
+#[test]
+fn end_of_line_auto() {
+    let config = LangConfig {
+        end_of_line: EndOfLine::Auto,
+        ..LANG_CONFIG
+    };
+    let input = "\u{feff}// one\r\n#region two\r\n#macro THREE \\\r\n    3\r\n#endregion\r\nvar a = 1;\n";
+    let format = "\u{feff}// one\r\n#region two\r\n#macro THREE \\\r\n             3\r\n#endregion\r\nvar a = 1;\r\n";
+    assert_eq!(run_test_with(input, &config), format);
+
+    let input = "var a = 1;\nb = @\"x\r\ny\";\r\n";
+    let format = "var a = 1;\nb = @\"x\r\ny\";\n";
+    assert_eq!(run_test_with(input, &config), format);
+}
+
+#[test]
+fn end_of_line_lf() {
+    let input = "// one\r\n#region two\r\n#macro THREE 3\r\n#endregion\r\n/* four\r\n*/\r\n";
+    let format = "// one\n#region two\n#macro THREE 3\n#endregion\n/* four\n*/\n";
+    assert_eq!(run_test_with(input, &LANG_CONFIG), format);
+
+    let input = "a = @\"x\r\ny\";\r\nb = \"z\";\r\n";
+    let format = "a = @\"x\r\ny\";\nb = \"z\";\n";
+    assert_eq!(run_test_with(input, &LANG_CONFIG), format);
+}
+
+#[test]
+fn byte_order_mark_only_at_start() {
+    let input = "\u{feff}a = \"\u{feff}\";\n";
+    assert_eq!(run_test(input), input);
+
+    let input = "a = 1;\n\u{feff}b = 2;\n";
+    assert!(run_test(input).contains('\u{feff}'));
+}
+
+#[test]
+fn editorconfig() {
+    let temp = TempProject::new("editorconfig");
+    let a = temp.write("a.gml", "");
+    let b = temp.write("scripts/b.gml", "");
+    let b_text = temp.write("scripts/b.txt", "");
+
+    temp.write(
+        ".editorconfig",
+        "root = true
+
+[*]
+indent_style = tab
+end_of_line = CRLF
+max_line_length = 100
+
+[*.{gml,yy}]
+indent_style = space
+indent_size = 2
+insert_final_newline = false
+",
+    );
+    let scripts_editorconfig = temp.write(
+        "scripts/.editorconfig",
+        "[*.gml]\nindent_size = tab\ntab_width = 3\nend_of_line = cr\n",
+    );
+
+    let config_resolver = ConfigResolver::default();
+    let resolved = config_resolver.resolve(&a).unwrap();
+    assert!(resolved.lang_config.use_spaces);
+    assert_eq!(resolved.lang_config.space_size, 2);
+    assert_eq!(resolved.lang_config.newlines_at_end, 0);
+    assert_eq!(resolved.lang_config.end_of_line, EndOfLine::Crlf);
+    assert!(!resolved.to_toml().contains("max_line_length"));
+
+    // nearer files win, even with a value we can't use, like `cr`.
+    let resolved = config_resolver.resolve(&b).unwrap();
+    assert_eq!(resolved.lang_config.space_size, 3);
+    assert_eq!(resolved.lang_config.end_of_line, EndOfLine::Auto);
+    assert_eq!(
+        resolved.sources["space_size"],
+        ConfigSource::File(scripts_editorconfig.canonicalize().unwrap())
+    );
+
+    let resolved = config_resolver.resolve(&b_text).unwrap();
+    assert!(!resolved.lang_config.use_spaces);
+
+    // gml_fmt.toml wins where both set an option.
+    temp.write("gml_fmt.toml", "space_size = 8\n");
+    let resolved = config_resolver.resolve(&a).unwrap();
+    assert_eq!(resolved.lang_config.space_size, 8);
+    assert_eq!(resolved.lang_config.newlines_at_end, 0);
+}
+
+#[test]
+fn config_path_overrides() {
+    let temp = TempProject::new("config_path_overrides");
+    let extension_file = temp.write("extensions/wrapper/a.gml", "");
+    let script = temp.write("scripts/a.gml", "");
+    let generated = temp.write("scripts/b.gen.gml", "");
+
+    temp.write(
+        "gml_fmt.toml",
+        r#"root = true
+space_size = 2
+
+[[overrides]]
+files = ["extensions/**", "*.gen.gml"]
+use_spaces = false
+space_size = 8
+
+[[overrides]]
+files = ["*.gen.gml"]
+ignore = true
+"#,
+    );
+
+    let config_resolver = ConfigResolver::default();
+    let resolved = config_resolver.resolve(&script).unwrap();
+    assert_eq!(resolved.lang_config.space_size, 2);
+    assert!(resolved.lang_config.use_spaces);
+    assert!(!resolved.ignore);
+
+    let resolved = config_resolver.resolve(&extension_file).unwrap();
+    assert_eq!(resolved.lang_config.space_size, 8);
+    assert!(!resolved.lang_config.use_spaces);
+    assert!(!resolved.ignore);
+
+    let resolved = config_resolver.resolve(&generated).unwrap();
+    assert!(resolved.ignore);
+    assert!(resolved.to_toml().starts_with("ignore = true # "));
+
+    // a nearer config file still wins over an override further up.
+    temp.write("extensions/wrapper/gml_fmt.toml", "space_size = 3\n");
+    let resolved = config_resolver.resolve(&extension_file).unwrap();
+    assert_eq!(resolved.lang_config.space_size, 3);
+    assert!(!resolved.lang_config.use_spaces);
+
+    temp.write(
+        "gml_fmt.toml",
+        "[[overrides]]\nfiles = [\"*\"]\n\n[[overrides]]\nfiles = [\"*\"]\nspace_size = \"2\"\n",
+    );
+    let error = config_resolver.resolve(&script).unwrap_err();
+    assert_eq!(error.line, Some(6));
+    assert_eq!(error.key.as_deref(), Some("space_size"));
+
+    temp.write("gml_fmt.toml", "[[overrides]]\nspace_size = 2\n");
+    let error = config_resolver.resolve(&script).unwrap_err();
+    assert!(error.to_string().ends_with("every override needs a `files` list"));
+}
+
+#[test]
+fn walking_folders() {
+    let temp = TempProject::new("walking_folders");
+    let root = &temp.root;
+    for file in &[
+        "scripts/a.gml",
+        "scripts/keep.gml",
+        "scripts/skip.gml",
+        "scripts/nested/b.gml",
+        "datafiles/c.gml",
+        ".hidden/d.gml",
+        "build/e.gml",
+        "extensions/f.gml",
+        "extensions/f.txt",
+    ] {
+        temp.write(file, "");
+    }
+    temp.write(".gitignore", "# build output\nbuild/\nextensions/*.gml\n");
+    temp.write("scripts/.gml_fmtignore", "*.gml\n!a.gml\n!keep.gml\n");
+
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(root, temp.path("scripts/nested/loop")).unwrap();
+
+    let files_with = |include: &[&str], exclude: &[&str]| {
+        let include: Vec<String> = include.iter().map(|glob| glob.to_string()).collect();
+        let exclude: Vec<String> = exclude.iter().map(|glob| glob.to_string()).collect();
+        let config = Config::with_filters(root.to_owned(), PrintFlags::empty(), false, &include, &exclude).unwrap();
+
+        let files: Vec<PathBuf> = config
+            .files
+            .iter()
+            .map(|file| file.strip_prefix(root).unwrap().to_owned())
+            .collect();
+        (files, config.warnings)
+    };
+
+    let (files, warnings) = files_with(&[], &[]);
+    assert_eq!(
+        files,
+        vec![
+            PathBuf::from("datafiles/c.gml"),
+            PathBuf::from("scripts/a.gml"),
+            PathBuf::from("scripts/keep.gml"),
+        ]
+    );
+    #[cfg(unix)]
+    assert_eq!(warnings.len(), 1);
+    #[cfg(not(unix))]
+    assert!(warnings.is_empty());
+
+    let (files, _) = files_with(&["scripts"], &["keep.gml"]);
+    assert_eq!(files, vec![PathBuf::from("scripts/a.gml")]);
+
+    let (files, _) = files_with(&[], &["datafiles"]);
+    assert_eq!(
+        files,
+        vec![PathBuf::from("scripts/a.gml"), PathBuf::from("scripts/keep.gml")]
+    );
+}
+
+#[test]
+fn config_include_and_exclude() {
+    let temp = TempProject::new("config_include_and_exclude");
+    for file in &["scripts/a.gml", "scripts/generated/b.gml", "notes/c.gml"] {
+        temp.write(file, "");
+    }
+    temp.write(
+        "gml_fmt.toml",
+        "root = true\ninclude = [\"scripts\"]\nexclude = [\"generated\"]\n",
+    );
+
+    let config_resolver = ConfigResolver::default();
+    let ignored = |file: &str| config_resolver.resolve(&temp.path(file)).unwrap().ignore;
+    assert!(!ignored("scripts/a.gml"));
+    assert!(ignored("scripts/generated/b.gml"));
+    assert!(ignored("notes/c.gml"));
+
+    temp.write("gml_fmt.toml", "include = \"scripts\"\n");
+    let error = config_resolver.resolve(&temp.path("scripts/a.gml")).unwrap_err();
+    assert_eq!(error.key.as_deref(), Some("include"));
+}
+
+#[test]
+fn project_files_2_2() {
+    let temp = TempProject::new("project_files_2_2");
+    let script = temp.path("scripts/scr_player_move");
+    let object = temp.path("objects/obj_player");
+
+    temp.write(
+        "game.yyp",
+        r#"{
+    "modelName": "GMProject",
+    "resources": [
+        {
+            "Key": "1",
+            "Value": {
+                "resourcePath": "scripts\\scr_player_move\\scr_player_move.yy",
+                "resourceType": "GMScript"
+            }
+        },
+        {
+            "Key": "2",
+            "Value": {
+                "resourcePath": "objects\\obj_player\\obj_player.yy",
+                "resourceType": "GMObject"
+            }
+        },
+        {
+            "Key": "3",
+            "Value": {
+                "resourcePath": "sprites\\spr_player\\spr_player.yy",
+                "resourceType": "GMSprite"
+            }
+        }
+    ]
+}"#,
+    );
+    temp.write(
+        "scripts/scr_player_move/scr_player_move.yy",
+        r#"{"modelName": "GMScript", "name": "scr_player_move"}"#,
+    );
+    temp.write(
+        "objects/obj_player/obj_player.yy",
+        r#"{
+    "modelName": "GMObject",
+    "name": "obj_player",
+    "eventList": [
+        {"IsDnD": false, "collisionObjectId": "00000000-0000-0000-0000-000000000000", "enumb": 0, "eventtype": 0},
+        {"IsDnD": false, "collisionObjectId": "1d0c9a2e-0000-0000-0000-000000000000", "enumb": 0, "eventtype": 4},
+        {"IsDnD": true, "collisionObjectId": "00000000-0000-0000-0000-000000000000", "enumb": 0, "eventtype": 3},
+        {"IsDnD": false, "collisionObjectId": "00000000-0000-0000-0000-000000000000", "enumb": 64, "eventtype": 8}
+    ]
+}"#,
+    );
+    for file in &[
+        "scripts/scr_player_move/scr_player_move.gml",
+        "objects/obj_player/Create_0.gml",
+        "objects/obj_player/Collision_1d0c9a2e-0000-0000-0000-000000000000.gml",
+        "objects/obj_player/Step_0.gml",
+        "scripts/scr_orphan/scr_orphan.gml",
+    ] {
+        temp.write(file, "");
+    }
+
+    let project = Project::read(&Project::find(&temp.root).unwrap()).unwrap();
+    let names: Vec<&str> = project
+        .resources
+        .iter()
+        .map(|resource| resource.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["obj_player", "scr_player_move"]);
+    assert_eq!(project.resources[0].kind, ResourceKind::Object);
+    assert_eq!(
+        project.resources[0].files,
+        vec![
+            object.join("Create_0.gml"),
+            object.join("Collision_1d0c9a2e-0000-0000-0000-000000000000.gml"),
+        ]
+    );
+    // the draw gui event is listed, but its file is missing.
+    assert_eq!(project.warnings.len(), 1);
+
+    let config = Config::from_project(&project, PrintFlags::empty(), &[], &[], &["objects".to_owned()]).unwrap();
+    assert_eq!(config.files, vec![script.join("scr_player_move.gml")]);
+
+    let resources = vec!["scr_player_mov".to_owned()];
+    let error = Config::from_project(&project, PrintFlags::empty(), &resources, &[], &[])
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "the project has no script or object called `scr_player_mov`, did you mean `scr_player_move`?"
+    );
+}
+
+#[test]
+fn project_files_2_3() {
+    let temp = TempProject::new("project_files_2_3");
+
+    let project_file = temp.write(
+        "game.yyp",
+        r#"{
+  "resources": [
+    {"id":{"name":"scr_jump","path":"scripts/scr_jump/scr_jump.yy",},"order":0,},
+    {"id":{"name":"obj_enemy","path":"objects/obj_enemy/obj_enemy.yy",},"order":1,},
+  ],
+  "resourceType": "GMProject",
+}"#,
+    );
+    temp.write(
+        "scripts/scr_jump/scr_jump.yy",
+        r#"{"isDnD":false,"name":"scr_jump","resourceType":"GMScript",}"#,
+    );
+    temp.write(
+        "objects/obj_enemy/obj_enemy.yy",
+        r#"{
+  "name": "obj_enemy",
+  "eventList": [
+    {"isDnD":false,"eventNum":0,"eventType":3,"collisionObjectId":null,"resourceType":"GMEvent",},
+    {"isDnD":false,"eventNum":0,"eventType":4,"collisionObjectId":{"name":"obj_wall","path":"objects/obj_wall/obj_wall.yy",},},
+  ],
+  "resourceType": "GMObject",
+}"#,
+    );
+    temp.write("scripts/scr_jump/scr_jump.gml", "");
+    let step = temp.write("objects/obj_enemy/Step_0.gml", "");
+    let collision = temp.write("objects/obj_enemy/Collision_obj_wall.gml", "");
+
+    let project = Project::read(&project_file).unwrap();
+    assert!(project.warnings.is_empty());
+
+    let resources = vec!["obj_enemy".to_owned()];
+    let config = Config::from_project(&project, PrintFlags::empty(), &resources, &[], &[]).unwrap();
+    assert_eq!(config.files, vec![step, collision]);
+}
+
+#[test]
+fn non_utf8_files() {
+    let temp = TempProject::new("non_utf8_files");
+    let a = temp.write("a.gml", b"\x00\x01binary");
+    let b = temp.write("b.gml", b"// caf\xe9\nvar  a=\"\x80\";");
+    let c = temp.write("c.gml", b"\xff\xfea\x00=\x001\x00");
+
+    let config = Config::new(temp.root.clone(), PrintFlags::OVERWRITE, false).unwrap();
+    run_with_config(&config, &ConfigResolver::default()).unwrap();
+
+    assert_eq!(fs::read(a).unwrap(), b"\x00\x01binary");
+    assert_eq!(fs::read(b).unwrap(), b"// caf\xe9\nvar a = \"\x80\";\n");
+    assert_eq!(fs::read(c).unwrap(), b"\xff\xfea\x00 \x00=\x00 \x001\x00;\x00\n\x00");
+}
+
+#[test]
+fn failed_files_make_an_error() {
+    let temp = TempProject::new("failed_files_make_an_error");
+    let good = temp.write("a.gml", "var  a=1;");
+    temp.write("b.gml", "if (a {");
+    temp.write("c.gml", "if (b {");
+
+    // the files which can be formatted still are.
+    let config = Config::new(temp.root.clone(), PrintFlags::OVERWRITE, false).unwrap();
+    let error = run_with_config(&config, &ConfigResolver::default()).unwrap_err();
+    assert_eq!(error.to_string(), "2 files could not be formatted");
+    assert_eq!(fs::read_to_string(&good).unwrap(), "var a = 1;\n");
+}
+
+#[test]
+fn backup_and_restore() {
+    let temp = TempProject::new("backup_and_restore");
+    let a = temp.write("scripts/a.gml", "var  a=1;");
+    let b = temp.write("b.gml", "var b = 2;\n");
+    let b_modified = fs::metadata(&b).unwrap().modified().unwrap();
+
+    let config = Config::new(temp.root.clone(), PrintFlags::OVERWRITE | PrintFlags::BACKUP, false).unwrap();
+    run_with_config(&config, &ConfigResolver::default()).unwrap();
+    assert_eq!(fs::read_to_string(&a).unwrap(), "var a = 1;\n");
+    assert_eq!(fs::metadata(&b).unwrap().modified().unwrap(), b_modified);
+
+    // running again changes nothing, so makes no second backup.
+    run_with_config(&config, &ConfigResolver::default()).unwrap();
+
+    let restored = restore_latest(&temp.root).unwrap().unwrap();
+    assert_eq!(restored.files, vec![config.root.join("scripts").join("a.gml")]);
+    assert_eq!(fs::read_to_string(&a).unwrap(), "var  a=1;");
+    assert!(restore_latest(&temp.root).unwrap().is_none());
+}
+
+#[test]
+fn format_checked_passes() {
+    assert_eq!(format_checked("var  a=1", &LANG_CONFIG).unwrap(), "var a = 1;\n");
+
+    match format_checked("if (a {", &LANG_CONFIG) {
+        Err(CheckError::Format(FormatError::Parse { .. })) => {}
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn format_str_and_file() {
+    let lang_config = LangConfig::builder()
+        .space_size(2)
+        .end_of_line(EndOfLine::Lf)
+        .build()
+        .unwrap();
+    assert_eq!(
+        format_str("if (a) {\nb=1;\n}", &lang_config).unwrap(),
+        "if (a) {\n  b = 1;\n}\n"
+    );
+    match format_str("if (a {", &lang_config) {
+        Err(FormatError::Parse { .. }) => {}
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+    let error = format_str("switch (a) {\n  x;\n}", &lang_config).unwrap_err();
+    match &error {
+        FormatError::Parse { line, column, span, .. } => {
+            assert_eq!((*line, *column, *span), (2, 3, Span { start: 15, end: 16 }))
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+    assert_eq!(error.to_string(), "2:3: could not parse: `x` in a switch statement");
+
+    let error = LangConfig::builder().space_size(0).build().unwrap_err();
+    assert_eq!(error.key.as_deref(), Some("space_size"));
+
+    let temp = TempProject::new("format_str_and_file");
+    temp.write(
+        "gml_fmt.toml",
+        "space_size = 3\n[[overrides]]\nfiles = [\"b.gml\"]\nignore = true\n",
+    );
+    let a = temp.write("a.gml", "if (a) {\nb=1;\n}");
+    let b = temp.write("b.gml", "if (a) {\nb=1;\n}");
+
+    let config_resolver = ConfigResolver::new(&temp.root).unwrap();
+    assert_eq!(
+        format_file(&a, &config_resolver).unwrap().as_deref(),
+        Some("if (a) {\n   b = 1;\n}\n")
+    );
+    assert_eq!(format_file(&b, &config_resolver).unwrap(), None);
+    match format_file(&temp.path("c.gml"), &config_resolver) {
+        Err(FormatError::Io { .. }) => {}
+        other => panic!("expected an IO error, got {:?}", other),
+    }
+
+    temp.write("sub/gml_fmt.toml", "space_size = \"three\"\n");
+    let d = temp.write("sub/d.gml", "");
+    match format_file(&d, &config_resolver) {
+        Err(FormatError::Config(_)) => {}
+        other => panic!("expected a config error, got {:?}", other),
+    }
+}
+
+#[test]
+fn visitors() {
+    #[derive(Default)]
+    struct Collector<'a> {
+        identifiers: Vec<&'a str>,
+        comments: Vec<&'a str>,
+    }
+
+    impl<'a> Visitor<'a> for Collector<'a> {
+        fn visit_expr(&mut self, expr: &ExprBoxInterior<'a>) {
+            if let Expr::Identifier { name, .. } = &expr.expr {
+                if let TokenType::Identifier(name) = name.token_type {
+                    self.identifiers.push(name);
+                }
+            }
+            walk_expr(self, expr);
+        }
+
+        fn visit_comments(&mut self, comments: &CommentsAndNewlines<'a>) {
+            for token in comments.iter().flatten() {
+                if let TokenType::Comment(comment) = token.token_type {
+                    self.comments.push(comment);
+                }
+            }
+        }
+    }
+
+    struct Renamer;
+
+    impl<'a> VisitorMut<'a> for Renamer {
+        fn visit_expr_mut(&mut self, expr: &mut ExprBoxInterior<'a>) {
+            if let Expr::Identifier { name, .. } = &mut expr.expr {
+                if name.token_type == TokenType::Identifier("a") {
+                    name.token_type = TokenType::Identifier("renamed");
+                }
+            }
+            walk_expr_mut(self, expr);
+        }
+    }
+
+    let source = "var a = b + // one\n c;\nif (a) { // two\n    d(a, [e]);\n}\n#macro F a.g\n";
+    let mut ast = Parser::new(source).build_ast().unwrap();
+
+    let mut collector = Collector::default();
+    walk_ast(&mut collector, &ast);
+    assert_eq!(collector.identifiers, vec!["a", "b", "c", "a", "d", "a", "e", "a", "g"]);
+    assert_eq!(collector.comments, vec!["// one", "// two"]);
+
+    walk_ast_mut(&mut Renamer, &mut ast);
+    let mut collector = Collector::default();
+    walk_ast(&mut collector, &ast);
+    assert_eq!(
+        collector.identifiers,
+        vec!["renamed", "b", "c", "renamed", "d", "renamed", "e", "renamed", "g"]
+    );
+}
+
+#[test]
+fn dump_ast_and_tokens() {
+    let source = "var a = 1; // hi\n";
+
+    let tokens: serde_json::Value = serde_json::from_str(&dump_tokens(source, DumpFormat::Json).unwrap()).unwrap();
+    let tokens = tokens.as_array().unwrap();
+    assert_eq!(tokens.len(), 7);
+    assert_eq!(tokens[1]["token_type"]["Identifier"], "a");
+    assert_eq!(tokens[1]["span"]["start"], 4);
+    assert_eq!(tokens[1]["span"]["end"], 5);
+    assert_eq!(tokens[5]["token_type"]["Comment"], "// hi");
+    assert_eq!(tokens[5]["column_number"], 11);
+
+    let ast: serde_json::Value = serde_json::from_str(&dump_ast(source, DumpFormat::Json).unwrap()).unwrap();
+    let declaration = &ast[0]["statement"]["VariableDeclList"];
+    assert_eq!(declaration["starting_var_type"]["token_type"], "Var");
+    let assign = &declaration["var_decl"]["lines"][0]["expr"]["var_expr"]["expr"]["Assign"];
+    assert_eq!(assign["right"]["expr"]["Literal"]["literal_token"]["span"]["start"], 8);
+    assert_eq!(ast[0]["has_semicolon"], true);
+
+    assert!(dump_ast(source, DumpFormat::Debug)
+        .unwrap()
+        .contains("VariableDeclList"));
+    match dump_ast("if (a {", DumpFormat::Json) {
+        Err(FormatError::Parse { .. }) => {}
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn spans_and_line_index() {
+    struct Spans<'s> {
+        source: &'s str,
+        statements: Vec<&'s str>,
+        exprs: Vec<&'s str>,
+    }
+
+    impl<'a, 's> Visitor<'a> for Spans<'s> {
+        fn visit_statement(&mut self, stmt: &StatementWrapper<'a>) {
+            self.statements.push(&self.source[stmt.span.start..stmt.span.end]);
+            walk_statement(self, stmt);
+        }
+
+        fn visit_expr(&mut self, expr: &ExprBoxInterior<'a>) {
+            self.exprs.push(&self.source[expr.span.start..expr.span.end]);
+            walk_expr(self, expr);
+        }
+    }
+
+    let source = "var a = b + c; // x\nif (a) {\n    d(\"é\", [f]);\n}\n#macro G (h * 2)\n";
+    let ast = Parser::new(source).build_ast().unwrap();
+    let mut spans = Spans {
+        source,
+        statements: Vec::new(),
+        exprs: Vec::new(),
+    };
+    walk_ast(&mut spans, &ast);
+    assert_eq!(
+        spans.statements,
+        vec![
+            "var a = b + c;",
+            "// x",
+            "\n",
+            "if (a) {\n    d(\"é\", [f]);\n}",
+            "{\n    d(\"é\", [f]);\n}",
+            "d(\"é\", [f]);",
+            "\n",
+            "#macro G (h * 2)",
+            "\n"
+        ]
+    );
+    assert_eq!(
+        spans.exprs,
+        vec![
+            "a = b + c",
+            "a",
+            "b + c",
+            "b",
+            "c",
+            "\n",
+            "(a)",
+            "a",
+            "d(\"é\", [f])",
+            "d",
+            "\"é\"",
+            "[f]",
+            "f",
+            "\n",
+            "(h * 2)",
+            "h * 2",
+            "h",
+            "2",
+            "\n"
+        ]
+    );
+
+    let line_index = LineIndex::new(source);
+    let tokens: serde_json::Value = serde_json::from_str(&dump_tokens(source, DumpFormat::Json).unwrap()).unwrap();
+    for token in tokens.as_array().unwrap() {
+        let start = token["span"]["start"].as_u64().unwrap() as usize;
+        let line_col = line_index.line_col(start);
+        assert_eq!(u64::from(line_col.line), token["line_number"].as_u64().unwrap());
+        assert_eq!(u64::from(line_col.column), token["column_number"].as_u64().unwrap());
+        assert_eq!(line_index.offset(line_col), Some(start));
+    }
+
+    // `é` is two bytes, but one UTF-16 unit.
+    let bracket = source.find('[').unwrap();
+    assert_eq!(line_index.line_col(bracket), LineCol { line: 2, column: 12 });
+    assert_eq!(line_index.line_col_utf16(bracket), LineCol { line: 2, column: 11 });
+    assert_eq!(line_index.offset_utf16(LineCol { line: 2, column: 11 }), Some(bracket));
+}
+
+#[test]
+fn cst_round_trips() {
+    let sources = [
+        "",
+        "\u{feff}var a = 1;\r\n",
+        "if a = 1 then b = 2 else   c = 3;\t\n",
+        "  // comment  \n/* multiline\n   comment */\nvar  a=1 ,b  = 2;;\n\n\n",
+        "#region Stuff   \r\n#macro A (1 + 2)\r\n#macro B:c \\\n    3\n#endregion\n",
+        "switch (a) {\n\tcase 1: b = \"é 😀\"; break;\n\tdefault:\n\t\texit;\n}\n",
+        "with (other) {\n    x = ds_map[? \"key\"] + grid[# 1, 2] + .5 + 3.;\n}\n",
+        "var a = 1, b\nc = function(d) constructor {\n    return new Thing(d);\n}\n",
+        "do { a++; } until (a >= 10)    // done\nfor (var i = 0; i < 10; i++) show_debug_message(i);",
+        "var a = `literal` ~ @\"verbatim\nstring\";",
+    ];
+
+    for source in sources.iter() {
+        let cst = build_cst(source).unwrap();
+        assert_eq!(cst.to_string(), *source);
+
+        let mut position = 0;
+        for token in cst.tokens() {
+            assert_eq!(token.span.start, position);
+            assert_eq!(&source[token.span.start..token.span.end], token.text);
+            position = token.span.end;
+        }
+        assert_eq!(position, source.len());
+    }
+
+    // `then` isn't a comment in the tree, but a token of the `if`.
+    let cst = build_cst("if a then b = 1;").unwrap();
+    let statement = match &cst.children[0] {
+        SyntaxElement::Node(node) => node,
+        other => panic!("expected the if statement, got {:?}", other),
+    };
+    assert_eq!(statement.kind, SyntaxKind::Statement);
+    assert_eq!(statement.to_string(), "if a then b = 1;");
+    let then = statement.children.iter().find_map(|child| match child {
+        SyntaxElement::Token(token) if token.text == "then" => Some(token),
+        _ => None,
+    });
+    assert!(then.is_some());
+    let nodes: Vec<String> = statement
+        .children
+        .iter()
+        .filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node.to_string()),
+            SyntaxElement::Token(_) => None,
+        })
+        .collect();
+    assert_eq!(nodes, vec!["a", "b = 1;"]);
+}
+
+#[test]
+fn source_map_and_cursor() {
+    let source = "var alpha=b+c;\nif (alpha) d(1);";
+    let (output, source_map) = format_with_source_map(source, &BRACES_CONFIG).unwrap();
+    assert_eq!(output, "var alpha = b + c;\nif (alpha) {\n    d(1);\n}\n");
+
+    for mapping in source_map.mappings() {
+        assert_eq!(
+            &output[mapping.output.start..mapping.output.end],
+            &source[mapping.original.start..mapping.original.end]
+        );
+    }
+    let d = source_map.mapping_at(output.find('d').unwrap()).unwrap();
+    assert_eq!(d.original_position, LineCol { line: 1, column: 11 });
+
+    // The inserted brace isn't in the source, so it keeps its distance from `alpha`, as far
+    // as the `d` after it.
+    let brace = output.find('{').unwrap();
+    assert_eq!(source_map.original_offset(brace), source.find('d').unwrap());
+    assert_eq!(
+        source_map.original_offset(output.find('d').unwrap()),
+        source.find('d').unwrap()
+    );
+
+    // Inside `alpha`, at `+`, and at the end.
+    assert_eq!(
+        format_with_cursor(source, 6, &BRACES_CONFIG).unwrap(),
+        (output.clone(), 6)
+    );
+    assert_eq!(source_map.output_offset(11), 14);
+    assert_eq!(source_map.output_offset(source.len()), output.find("\n}").unwrap());
+
+    // The byte order mark is put back in front of the output, and the map allows for it.
+    let (with_bom, cursor) = format_with_cursor("\u{feff}a=1;", 6, &LANG_CONFIG).unwrap();
+    assert_eq!(with_bom, "\u{feff}a = 1;\n");
+    assert_eq!(cursor, 8);
+}
+
+#[test]
+fn nesting_limit() {
+    let shallow = LangConfig::builder().max_depth(32).build().unwrap();
+    let nested = |depth: usize| format!("x = {}a{};", "(".repeat(depth), ")".repeat(depth));
+
+    assert_eq!(format_str(&nested(8), &shallow).unwrap(), format!("{}\n", nested(8)));
+    let error = format_str(&nested(100), &shallow).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "1:15: could not parse: code is nested more than 32 levels deep. Raise `max_depth` to format it."
+    );
+
+    // Blocks and `else if` chains nest too.
+    let blocks = |depth: usize| format!("{}a = 1;{}", "if a {\n".repeat(depth), "}\n".repeat(depth));
+    assert!(format_str(&blocks(40), &shallow).is_err());
+    let else_ifs = format!("{}a = 1;", "if a {} else ".repeat(40));
+    assert!(format_str(&else_ifs, &shallow).is_err());
+    assert!(format_str(&else_ifs, &LANG_CONFIG).is_ok());
+
+    // The default leaves room for deep code, and stops before running out of stack.
+    assert!(format_str(&nested(100), &LANG_CONFIG).is_ok());
+    assert!(format_str(&blocks(150), &LANG_CONFIG).is_ok());
+    assert!(format_str(&nested(1000), &LANG_CONFIG).is_err());
+    assert!(format_str(&blocks(1000), &LANG_CONFIG).is_err());
+
+    // A chain of operators isn't nested, however long it is, and nothing on the way
+    // through formatting recurses once per operator in it.
+    let chain = |operator: &str, terms: usize| format!("x = {}a;", format!("a {} ", operator).repeat(terms));
+    for operator in &["+", "*", "==", "<", "||", "&&", "^^"] {
+        assert_eq!(
+            format_str(&chain(operator, 5000), &shallow).unwrap(),
+            format!("{}\n", chain(operator, 5000))
+        );
+    }
+    let rewriting = LangConfig::builder()
+        .modernize_legacy_operators(true)
+        .insert_braces(true)
+        .build()
+        .unwrap();
+    assert_eq!(
+        format_str(&format!("if {}a x = 1;", "a <> ".repeat(5000)), &rewriting).unwrap(),
+        format!("if {}a {{\n    x = 1;\n}}\n", "a != ".repeat(5000))
+    );
+    let nested_sum = format!("x = {}a{}{};", "(".repeat(8), ")".repeat(8), " + a".repeat(60));
+    assert_eq!(format_str(&nested_sum, &shallow).unwrap(), format!("{}\n", nested_sum));
+
+    // A chain of accesses and calls is a level for every three links in it.
+    let access = |links: usize| format!("x = a{};", "[b]".repeat(links));
+    assert!(format_str(&access(60), &shallow).is_ok());
+    assert!(format_str(&access(DEFAULT_MAX_DEPTH * 4), &LANG_CONFIG).is_err());
+    assert!(LangConfig::builder().max_depth(0).build().is_err());
+
+    // A macro too deep to parse is kept as it is.
+    let deep_macro = format!("#macro A {}a{}\n", "(".repeat(100), ")".repeat(100));
+    assert_eq!(format_str(&deep_macro, &shallow).unwrap(), deep_macro);
+}